@@ -121,6 +121,7 @@
                     Type = "simple";
                     User = "root";
                     ExecStart = "${cfg.package}/bin/komari-monitor-rs --config ${configFile}";
+                    ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
                     Restart = "always";
                     RestartSec = 5;
                     StandardOutput = "journal";
@@ -72,46 +72,32 @@ load_config() {
     : "${GITHUB_REPO:=${DEFAULT_GITHUB_REPO}}"
 }
 
-# 保存配置到文件
+# 保存配置到文件 (通过 config set 原地修改，保留注释与其他配置项)
 save_config() {
-    mkdir -p "${CONFIG_DIR}"
-    cat > "${CONFIG_PATH}" <<EOF
-# Komari Monitor RS 配置文件
-# 由安装/管理脚本生成
-
-# 主端地址 (必需)
-http_server = "${HTTP_SERVER}"
-EOF
-
-    if [ -n "${WS_SERVER}" ]; then
-        echo "ws_server = \"${WS_SERVER}\"" >> ${CONFIG_PATH}
+    if [ ! -x "${INSTALL_PATH}" ]; then
+        log_error "未找到程序: ${INSTALL_PATH}，无法保存配置。"
+        return 1
     fi
 
-    cat >> ${CONFIG_PATH} <<EOF
-token = "${TOKEN}"
-
-# IP 提供商 (ipinfo / cloudflare)
-ip_provider = "${IP_PROVIDER}"
-
-# 功能开关
-terminal = ${TERMINAL_ENABLED}
-tls = ${TLS_ENABLED}
-ignore_unsafe_cert = ${IGNORE_CERT_ENABLED}
-
-# 性能设置
-fake = ${FAKE}
-realtime_info_interval = ${INTERVAL}
-billing_day = ${BILLING_DAY}
-
-# 日志等级 (error / warn / info / debug / trace)
-log_level = "${LOG_LEVEL}"
-
-# 自动升级 (0 = 禁用，其他数字为检查间隔小时数)
-auto_update = ${AUTO_UPDATE}
-update_repo = "${GITHUB_REPO}"
-EOF
-
-    chmod 600 "${CONFIG_PATH}"
+    local settings=(
+        http_server "${HTTP_SERVER}"
+        ws_server "${WS_SERVER}"
+        token "${TOKEN}"
+        ip_provider "${IP_PROVIDER}"
+        terminal "${TERMINAL_ENABLED}"
+        tls "${TLS_ENABLED}"
+        ignore_unsafe_cert "${IGNORE_CERT_ENABLED}"
+        fake "${FAKE}"
+        realtime_info_interval "${INTERVAL}"
+        billing_day "${BILLING_DAY}"
+        log_level "${LOG_LEVEL}"
+        auto_update "${AUTO_UPDATE}"
+        update_repo "${GITHUB_REPO}"
+    )
+    if ! "${INSTALL_PATH}" --config "${CONFIG_PATH}" config set -- "${settings[@]}"; then
+        log_error "保存配置失败，配置文件未修改。"
+        return 1
+    fi
 }
 
 # 交互式编辑配置
@@ -172,9 +158,10 @@ manage_edit_config() {
            12) read -p "auto_update (小时, 0=禁用): " AUTO_UPDATE ;;
            13) read -p "update_repo (owner/repo): " GITHUB_REPO ;;
             s|S)
-                save_config
-                log_info "配置已保存: ${CONFIG_PATH}"
-                return
+                if save_config; then
+                    log_info "配置已保存: ${CONFIG_PATH}"
+                    return
+                fi
                 ;;
             c|C)
                 log_warn "已取消修改。"
@@ -535,7 +522,7 @@ https://github.com/ilnli/komari-monitor-rs
   --ws-server <地址>        WebSocket 地址 (可选，自动推断)
   --auto-discovery <key>    自动发现密钥
   --terminal                启用 Web Terminal
-  --tls                     启用 TLS
+  --tls                     要求 TLS (主端地址需为 https/wss)
   --ignore-unsafe-cert      忽略证书验证
   --billing-day <1-31>      计费日 (默认: 1)
   --proxy [地址]            使用代理下载
@@ -836,6 +823,8 @@ After=network.target
 Type=simple
 User=root
 ExecStart=${INSTALL_PATH} --config ${CONFIG_PATH}
+ExecReload=/bin/kill -HUP \$MAINPID
+StateDirectory=komari-monitor
 Restart=always
 RestartSec=5
 StandardOutput=journal
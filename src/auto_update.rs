@@ -1,3 +1,4 @@
+use crate::config::Config;
 use log::{error, info, warn};
 use std::env;
 use std::fs;
@@ -48,8 +49,9 @@ fn is_newer_version(current: &str, remote: &str) -> bool {
     rem > cur
 }
 
-/// 检查并执行自动升级
-pub fn check_and_upgrade(repo: &str, ignore_unsafe_cert: bool) {
+/// 检查并执行自动升级，HTTPS 请求信任 `ca_file` 中的证书
+pub fn check_and_upgrade(config: &Config) {
+    let repo = &config.update_repo;
     let Some(arch) = get_arch_suffix() else {
         warn!("自动升级不支持当前平台");
         return;
@@ -58,7 +60,7 @@ pub fn check_and_upgrade(repo: &str, ignore_unsafe_cert: bool) {
     let api_url = GITHUB_API.replace("{repo}", repo);
 
     // 获取最新版本信息
-    let response = match http_get(&api_url, ignore_unsafe_cert) {
+    let response = match http_get(&api_url, config) {
         Ok(r) => r,
         Err(e) => {
             warn!("检查更新失败: {e}");
@@ -94,7 +96,7 @@ pub fn check_and_upgrade(repo: &str, ignore_unsafe_cert: bool) {
 
     let tmp_path = exe_path.with_extension("new");
 
-    if let Err(e) = download_file(&download_url, &tmp_path, ignore_unsafe_cert) {
+    if let Err(e) = download_file(&download_url, &tmp_path, config) {
         error!("下载新版本失败: {e}");
         return;
     }
@@ -133,9 +135,30 @@ pub fn check_and_upgrade(repo: &str, ignore_unsafe_cert: bool) {
         .spawn();
 }
 
+/// 下载可能耗时较长，不设置总超时
 #[cfg(feature = "ureq-support")]
-fn http_get(url: &str, _ignore_cert: bool) -> Result<String, String> {
-    ureq::get(url)
+fn update_agent(config: &Config) -> Result<ureq::Agent, String> {
+    let tls = crate::rustls_config::general_tls_config(config)?;
+    let proxy = crate::proxy::ProxySettings::from_config(config);
+    Ok(crate::rustls_config::ureq_agent(tls, proxy, None))
+}
+
+/// nyquest 使用系统的证书与代理配置，无法应用 `ca_file` 与 `proxy`
+#[cfg(feature = "nyquest-support")]
+fn update_client(config: &Config) -> Result<nyquest::BlockingClient, String> {
+    if config.ca_file.is_some() || config.proxy.is_some() {
+        return Err("nyquest 后端不支持 ca_file 与 proxy".to_string());
+    }
+    nyquest::ClientBuilder::default()
+        .user_agent("komari-monitor-rs")
+        .build_blocking()
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "ureq-support")]
+fn http_get(url: &str, config: &Config) -> Result<String, String> {
+    update_agent(config)?
+        .get(url)
         .header("User-Agent", "komari-monitor-rs")
         .call()
         .map_err(|e| e.to_string())?
@@ -145,19 +168,16 @@ fn http_get(url: &str, _ignore_cert: bool) -> Result<String, String> {
 }
 
 #[cfg(feature = "nyquest-support")]
-fn http_get(url: &str, _ignore_cert: bool) -> Result<String, String> {
+fn http_get(url: &str, config: &Config) -> Result<String, String> {
     use nyquest::Request;
-    let client = nyquest::ClientBuilder::default()
-        .user_agent("komari-monitor-rs")
-        .build_blocking()
-        .map_err(|e| e.to_string())?;
+    let client = update_client(config)?;
     let request = Request::get(url.to_string());
     let resp = client.request(request).map_err(|e| e.to_string())?;
     resp.text().map_err(|e| e.to_string())
 }
 
 #[cfg(not(any(feature = "ureq-support", feature = "nyquest-support")))]
-fn http_get(_url: &str, _ignore_cert: bool) -> Result<String, String> {
+fn http_get(_url: &str, _config: &Config) -> Result<String, String> {
     Err("未启用 HTTP 客户端".to_string())
 }
 
@@ -165,9 +185,10 @@ fn http_get(_url: &str, _ignore_cert: bool) -> Result<String, String> {
 fn download_file(
     url: &str,
     path: &std::path::Path,
-    _ignore_cert: bool,
+    config: &Config,
 ) -> Result<(), String> {
-    let resp = ureq::get(url)
+    let resp = update_agent(config)?
+        .get(url)
         .header("User-Agent", "komari-monitor-rs")
         .call()
         .map_err(|e| e.to_string())?;
@@ -181,13 +202,10 @@ fn download_file(
 fn download_file(
     url: &str,
     path: &std::path::Path,
-    _ignore_cert: bool,
+    config: &Config,
 ) -> Result<(), String> {
     use nyquest::Request;
-    let client = nyquest::ClientBuilder::default()
-        .user_agent("komari-monitor-rs")
-        .build_blocking()
-        .map_err(|e| e.to_string())?;
+    let client = update_client(config)?;
     let request = Request::get(url.to_string());
     let resp = client.request(request).map_err(|e| e.to_string())?;
     let bytes = resp.bytes().map_err(|e| e.to_string())?;
@@ -198,7 +216,7 @@ fn download_file(
 fn download_file(
     _url: &str,
     _path: &std::path::Path,
-    _ignore_cert: bool,
+    _config: &Config,
 ) -> Result<(), String> {
     Err("未启用 HTTP 客户端".to_string())
 }
@@ -1,3 +1,4 @@
+use crate::config::Config;
 use miniserde::{Deserialize, Serialize, json};
 use std::process::Stdio;
 use time::OffsetDateTime;
@@ -23,7 +24,7 @@ pub struct RemoteExecCallback {
 pub async fn exec_command(
     utf8_str: &str,
     callback_url: String,
-    ignore_unsafe_cert: &bool,
+    config: &Config,
 ) -> Result<(), String> {
     let remote_exec: RemoteExec =
         json::from_str(utf8_str).map_err(|_| "无法解析 RemoteExec".to_string())?;
@@ -68,8 +69,7 @@ pub async fn exec_command(
     let json_string = json::to_string(&reply);
     #[cfg(feature = "ureq-support")]
     {
-        use crate::utils::create_ureq_agent;
-        let agent = create_ureq_agent(*ignore_unsafe_cert);
+        let agent = crate::utils::panel_ureq_agent(config)?;
         if let Ok(req) = agent.post(callback_url).send(&json_string) {
             if req.status().is_success() {
                 Ok(())
@@ -85,7 +85,7 @@ pub async fn exec_command(
     {
         use nyquest::Body;
         use nyquest::Request;
-        let client = crate::utils::create_nyquest_client(*ignore_unsafe_cert);
+        let client = crate::utils::panel_nyquest_client(config)?;
         let body = Body::text(json_string, "application/json");
         let request = Request::post(callback_url).with_body(body);
 
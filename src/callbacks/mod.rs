@@ -2,14 +2,16 @@ use crate::callbacks::exec::exec_command;
 use crate::callbacks::ping::ping_target;
 use crate::callbacks::pty::{get_pty_ws_link, handle_pty_session};
 use crate::config::Config;
+use crate::reporter::heartbeat::Liveness;
+use crate::shutdown::Shutdown;
 use crate::utils::{ConnectionUrls, connect_ws};
 use futures::stream::{SplitSink, SplitStream};
 use futures::{SinkExt, StreamExt};
-use log::{error, info};
+use log::{error, info, warn};
 use miniserde::{Deserialize, Serialize, json};
 use std::sync::Arc;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, watch};
 use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
@@ -23,19 +25,30 @@ struct Msg {
 }
 
 type Reader = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
-type LockedWriter = Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>;
+pub type LockedWriter = Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>;
 
 pub async fn handle_callbacks(
-    config: &Config,
+    config: &watch::Receiver<Config>,
     connection_urls: &ConnectionUrls,
     reader: &mut Reader,
     locked_writer: &LockedWriter,
+    liveness: &Liveness,
+    shutdown: &Shutdown,
 ) -> () {
     while let Some(msg) = reader.next().await {
-        let Ok(msg) = msg else {
-            continue;
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("读取上报 WebSocket 时发生错误: {e}");
+                break;
+            }
         };
 
+        liveness.touch();
+        if msg.is_pong() || msg.is_ping() {
+            continue;
+        }
+
         let Ok(utf8) = msg.into_text() else {
             continue;
         };
@@ -49,29 +62,36 @@ pub async fn handle_callbacks(
         };
 
         let utf8_cloned = utf8.clone();
+        // 每条消息读取最新配置，热重载后立即启用或禁用终端与 Exec
+        let config = config.borrow().clone();
 
         match json.message.as_str() {
             "exec" => {
-                if config.terminal {
+                if config.exec_enabled() {
+                    let Some(guard) = shutdown.track() else {
+                        warn!("Agent 正在退出，忽略 Exec 请求");
+                        continue;
+                    };
                     tokio::spawn({
                         let utf8_cloned_for_exec = utf8_cloned.clone();
                         let exec_callback_url = connection_urls.exec_callback.clone();
-                        let ignore_unsafe_cert = config.ignore_unsafe_cert;
+                        let config = config.clone();
 
                         async move {
                             if let Err(e) = exec_command(
                                 &utf8_cloned_for_exec,
                                 exec_callback_url,
-                                &ignore_unsafe_cert,
+                                &config,
                             )
                             .await
                             {
                                 error!("Exec Error: {e}");
                             }
+                            drop(guard);
                         }
                     });
                 } else {
-                    error!("终端功能未启用");
+                    error!("Exec 功能未启用");
                 }
             }
 
@@ -98,6 +118,11 @@ pub async fn handle_callbacks(
 
             "terminal" => {
                 if config.terminal {
+                    let Some(guard) = shutdown.track() else {
+                        warn!("Agent 正在退出，忽略 Terminal 请求");
+                        continue;
+                    };
+                    let shutdown = shutdown.clone();
                     let ws_terminal_url = connection_urls.clone().ws_terminal.clone();
                     let config = config.clone();
                     let utf8_cloned = utf8_cloned.clone();
@@ -111,18 +136,20 @@ pub async fn handle_callbacks(
                             }
                         };
 
-                        let ws_stream =
-                            match connect_ws(&ws_url, config.tls, config.ignore_unsafe_cert).await {
-                                Ok(ws_stream) => ws_stream,
-                                Err(e) => {
-                                    error!("无法连接到 PTY Websocket: {e}");
-                                    return;
-                                }
-                            };
+                        let ws_stream = match connect_ws(&ws_url, &config).await {
+                            Ok(ws_stream) => ws_stream,
+                            Err(e) => {
+                                error!("无法连接到 PTY Websocket: {e}");
+                                return;
+                            }
+                        };
 
-                        if let Err(e) = handle_pty_session(ws_stream, &config.terminal_entry).await {
+                        if let Err(e) =
+                            handle_pty_session(ws_stream, &config.terminal_entry, shutdown).await
+                        {
                             error!("PTY Websocket 处理错误: {e}");
                         }
+                        drop(guard);
                     });
                 } else {
                     error!("终端功能未启用");
@@ -1,3 +1,4 @@
+use crate::shutdown::Shutdown;
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
 use miniserde::{Deserialize, Serialize};
@@ -24,7 +25,11 @@ pub fn get_pty_ws_link(utf8_str: &str, ws_terminal_url: &str) -> Result<String,
     ))
 }
 
-pub async fn handle_pty_session<S>(ws_stream: WebSocketStream<S>, cmd: &str) -> Result<(), String>
+pub async fn handle_pty_session<S>(
+    ws_stream: WebSocketStream<S>,
+    cmd: &str,
+    mut shutdown: Shutdown,
+) -> Result<(), String>
 where
     S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
@@ -130,6 +135,7 @@ where
     tokio::select! {
         _ = pty_to_ws_task => info!("PTY -> WebSocket 任务结束。"),
         _ = ws_to_pty_task => info!("WebSocket -> PTY 任务结束。"),
+        () = shutdown.recv() => info!("Agent 正在退出，关闭终端会话。"),
     }
 
     info!("正在关闭会话，终止子进程...");
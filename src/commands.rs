@@ -0,0 +1,247 @@
+use crate::command_parser::{EXIT_CONFIG, EXIT_FAILURE, EXIT_OK, EXIT_UNREACHABLE, EXIT_USAGE};
+use crate::config::Config;
+use crate::data_struct::{BasicInfo, RealTimeInfo};
+use crate::get_info::network::filter::InterfaceFilter;
+use crate::get_info::network::traffic_control::agent_running;
+use crate::get_info::network::traffic_stats::TrafficStats;
+use crate::reporter::failover::active_endpoint_file;
+use crate::rustls_config::panel_tls_config;
+use crate::storage::instance_dir;
+use crate::utils::{build_urls, connect_ws, pretty_json};
+use miniserde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use sysinfo::{CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, Networks, RefreshKind};
+
+/// 隐去 URL 中的 token 参数
+fn redact_url(url: &str, token: &str) -> String {
+    url.replace(&format!("token={token}"), "token=******")
+}
+
+/// `check`: 校验配置并尝试连接主端 (仅建立 WebSocket 连接，不上报任何数据)
+pub async fn check(config: &Config, path: &Path) -> i32 {
+    println!("配置文件: {} (有效)", path.display());
+    println!("数据目录: {}", instance_dir(config).display());
+
+    // 依次检查顶层主端与各 [server] 配置块，任一主端无法连接即视为失败
+    let mut code = EXIT_OK;
+    for server in config.servers() {
+        let prefix = server
+            .server_name
+            .as_ref()
+            .map_or_else(String::new, |name| format!("[{name}] "));
+        let result = check_server(&server, &prefix).await;
+        if result != EXIT_OK && code != EXIT_CONFIG {
+            code = result;
+        }
+    }
+    code
+}
+
+async fn check_server(config: &Config, prefix: &str) -> i32 {
+    let endpoints = match build_urls(config) {
+        Ok(endpoints) => endpoints,
+        Err(e) => {
+            eprintln!("{prefix}错误: {e}");
+            return EXIT_CONFIG;
+        }
+    };
+    if let Err(e) = panel_tls_config(config) {
+        eprintln!("{prefix}错误: {e}");
+        return EXIT_CONFIG;
+    }
+
+    // 多个地址时依次测试主地址与各备用地址
+    let mut code = EXIT_OK;
+    for (index, urls) in endpoints.iter().enumerate() {
+        let role = match (endpoints.len(), index) {
+            (1, _) => String::new(),
+            (_, 0) => " (主地址)".to_string(),
+            (_, n) => format!(" (备用地址 {n})"),
+        };
+        println!("{prefix}主端地址: {}{role}", urls.endpoint);
+        println!(
+            "{prefix}WebSocket: {}",
+            redact_url(&urls.ws_real_time, &config.token)
+        );
+
+        let started = Instant::now();
+        match connect_ws(&urls.ws_real_time, config).await {
+            Ok(mut ws_stream) => {
+                let _ = ws_stream.close(None).await;
+                println!(
+                    "{prefix}连接主端: 成功 ({} ms)",
+                    started.elapsed().as_millis()
+                );
+            }
+            Err(e) => {
+                eprintln!("{prefix}连接主端: 失败 ({e})");
+                code = EXIT_UNREACHABLE;
+            }
+        }
+    }
+    code
+}
+
+#[derive(Serialize)]
+struct AgentInfo {
+    basic_info: BasicInfo,
+    realtime_info: RealTimeInfo,
+    endpoints: Vec<ActiveEndpoint>,
+}
+
+/// 每个主端当前使用的地址
+#[derive(Serialize)]
+struct ActiveEndpoint {
+    /// `[server]` 配置块名称，顶层主端为 null
+    server: Option<String>,
+    endpoint: String,
+    /// 是否为备用地址
+    backup: bool,
+}
+
+/// 各主端当前使用的地址: Agent 运行中时读取上报端记录的地址，否则为主地址
+fn active_endpoints(config: &Config) -> Vec<ActiveEndpoint> {
+    let data_dir = instance_dir(config);
+    let running = agent_running(&data_dir);
+    let mut endpoints = Vec::new();
+    for server in config.servers() {
+        let Some(primary) = build_urls(&server)
+            .ok()
+            .and_then(|urls| urls.into_iter().next())
+        else {
+            continue;
+        };
+        let recorded = running
+            .then(|| {
+                let path = active_endpoint_file(&data_dir, server.server_name.as_deref());
+                fs::read_to_string(path).ok()
+            })
+            .flatten()
+            .filter(|endpoint| !endpoint.is_empty());
+        let endpoint = recorded.unwrap_or_else(|| primary.endpoint.clone());
+        endpoints.push(ActiveEndpoint {
+            server: server.server_name,
+            backup: endpoint != primary.endpoint,
+            endpoint,
+        });
+    }
+    endpoints
+}
+
+/// `info`: 输出 Basic Info 与一次实时采样，不连接主端
+pub async fn info(config: &Config) -> i32 {
+    let mut sysinfo_sys = sysinfo::System::new();
+    sysinfo_sys.refresh_cpu_list(
+        CpuRefreshKind::nothing()
+            .without_cpu_usage()
+            .without_frequency(),
+    );
+    sysinfo_sys.refresh_memory_specifics(MemoryRefreshKind::everything());
+    let basic_info = BasicInfo::build(&sysinfo_sys, config).await;
+
+    // 使用流量统计的内存副本，避免与运行中的 Agent 同时写入
+    let mut traffic_stats = TrafficStats::read(&instance_dir(config)).unwrap_or_default();
+    traffic_stats.path = PathBuf::new();
+    traffic_stats.timezone.clone_from(&config.billing_timezone);
+
+    let interface_filter = InterfaceFilter::from_config(config);
+    let mut networks = Networks::new_with_refreshed_list();
+    let mut disks = Disks::new();
+    let refresh = |sys: &mut sysinfo::System| {
+        sys.refresh_specifics(
+            RefreshKind::nothing()
+                .with_cpu(CpuRefreshKind::everything().without_frequency())
+                .with_memory(MemoryRefreshKind::everything()),
+        );
+    };
+
+    // CPU 使用率与网速需要两次采样才能计算
+    refresh(&mut sysinfo_sys);
+    RealTimeInfo::build(
+        &sysinfo_sys,
+        &networks,
+        &interface_filter,
+        &disks,
+        &mut traffic_stats,
+        config.realtime_info_interval,
+        config.fake,
+    );
+    tokio::time::sleep(Duration::from_millis(config.realtime_info_interval)).await;
+    refresh(&mut sysinfo_sys);
+    networks.refresh(true);
+    disks.refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
+    let realtime_info = RealTimeInfo::build(
+        &sysinfo_sys,
+        &networks,
+        &interface_filter,
+        &disks,
+        &mut traffic_stats,
+        config.realtime_info_interval,
+        config.fake,
+    );
+
+    let info = AgentInfo {
+        basic_info,
+        realtime_info,
+        endpoints: active_endpoints(config),
+    };
+    println!("{}", pretty_json(&miniserde::json::to_string(&info)));
+    EXIT_OK
+}
+
+/// `print-config`: 输出生效的配置 (含默认值)，token 已隐去
+///
+/// `--origin` 时每行一个配置项，并注明来自哪个文件的哪一行、环境变量、`--set` 或默认值。
+pub fn print_config(config: &Config, origin: bool) -> i32 {
+    let config = config.redacted();
+    if origin {
+        print!("{}", config.render_origins());
+    } else {
+        print!("{}", config.render());
+    }
+    EXIT_OK
+}
+
+/// `config set`: 修改配置文件中的配置项，保留注释、未知配置项与原有顺序
+///
+/// 只读取配置文件本身，不应用环境变量，避免把环境变量的值写入文件。
+/// 多个配置项全部有效时才一次性写入，任一无效则不修改文件。
+pub fn config_set(path: &Path, settings: &[(String, Vec<String>)]) -> i32 {
+    let mut config = match Config::from_file(path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("错误: {e}");
+            return EXIT_CONFIG;
+        }
+    };
+    for (key, values) in settings {
+        if let Err(e) = config.set_values(key, values) {
+            eprintln!("错误: {e}");
+            return EXIT_USAGE;
+        }
+    }
+    if let Err(e) = config.save(path) {
+        eprintln!("错误: {e}");
+        return EXIT_FAILURE;
+    }
+    EXIT_OK
+}
+
+/// `config get`: 输出配置项的生效值，可重复的配置项每行一个
+///
+/// 与 `print-config` 相同，token 与代理密码已隐去。
+pub fn config_get(config: &Config, key: &str) -> i32 {
+    let Some(values) = config.redacted().get(key) else {
+        eprintln!("错误: 未知配置项: {key}");
+        return EXIT_USAGE;
+    };
+    if values.is_empty() {
+        return EXIT_FAILURE;
+    }
+    for value in values {
+        println!("{value}");
+    }
+    EXIT_OK
+}
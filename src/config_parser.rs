@@ -0,0 +1,544 @@
+use std::fmt::{Display, Formatter};
+
+/// 配置文件中的一个 `key = value` 项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    /// 所在行 (从 1 开始)
+    pub line: usize,
+    /// 配置项名称的起始列 (从 1 开始，按字符计)
+    pub key_column: usize,
+    /// 值的起始列
+    pub value_column: usize,
+    /// 所在的配置块 (如 `[server]`)，顶层配置项为 None
+    pub section: Option<Section>,
+}
+
+/// 配置块标题 `[name]`，配置块一直持续到下一个标题或文件末尾
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    /// 标题所在行，同名的多个配置块以此区分
+    pub line: usize,
+}
+
+/// 带位置信息的解析错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// 定位到配置项名称
+    pub fn at_key(entry: &Entry, message: impl Into<String>) -> Self {
+        Self::new(entry.line, entry.key_column, message)
+    }
+
+    /// 定位到配置项的值
+    pub fn at_value(entry: &Entry, message: impl Into<String>) -> Self {
+        Self::new(entry.line, entry.value_column, message)
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "第 {} 行第 {} 列: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// 解析配置文件
+///
+/// 语法:
+/// - 空行与 `#` 开头的行为注释
+/// - `key = value`，值可以加双引号；未加引号的值中，空白后的 `#` 开始行内注释
+/// - 双引号内支持 `\"`、`\\`、`\n`、`\t` 转义，`#` 不会被视为注释
+/// - `[name]` 开始一个配置块，之后的配置项属于该配置块
+pub fn parse(content: &str) -> Result<Vec<Entry>, ParseError> {
+    let mut entries = Vec::new();
+    let mut section = None;
+    for (index, raw) in content.lines().enumerate() {
+        if let Some(header) = parse_section(raw, index + 1)? {
+            section = Some(header);
+        } else if let Some((mut entry, _)) = parse_line(raw, index + 1)? {
+            entry.section.clone_from(&section);
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// 解析配置块标题 `[name]`，不是标题时返回 None
+fn parse_section(raw: &str, line: usize) -> Result<Option<Section>, ParseError> {
+    let chars: Vec<char> = raw.chars().collect();
+    let start = skip_whitespace(&chars, 0);
+    if chars.get(start) != Some(&'[') {
+        return Ok(None);
+    }
+
+    let name_start = start + 1;
+    let mut pos = name_start;
+    while pos < chars.len() && is_key_char(chars[pos]) {
+        pos += 1;
+    }
+    match chars.get(pos) {
+        Some(']') if pos > name_start => {}
+        Some(']') => return Err(ParseError::new(line, pos + 1, "配置块名称为空")),
+        Some(c) => {
+            return Err(ParseError::new(
+                line,
+                pos + 1,
+                format!("配置块名称中存在无效字符 '{c}'"),
+            ));
+        }
+        None => return Err(ParseError::new(line, pos + 1, "配置块标题缺少 ']'")),
+    }
+
+    let rest = skip_whitespace(&chars, pos + 1);
+    if rest < chars.len() && chars[rest] != '#' {
+        return Err(ParseError::new(line, rest + 1, "配置块标题后存在多余内容"));
+    }
+    Ok(Some(Section {
+        name: chars[name_start..pos].iter().collect(),
+        line,
+    }))
+}
+
+/// 解析一行，同时返回值的结束位置 (按字符计，不含)
+fn parse_line(raw: &str, line: usize) -> Result<Option<(Entry, usize)>, ParseError> {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut pos = skip_whitespace(&chars, 0);
+    // 兼容带 BOM 的文件
+    if line == 1 && chars.get(pos) == Some(&'\u{feff}') {
+        pos = skip_whitespace(&chars, pos + 1);
+    }
+    if pos >= chars.len() || chars[pos] == '#' {
+        return Ok(None);
+    }
+
+    let key_start = pos;
+    while pos < chars.len() && is_key_char(chars[pos]) {
+        pos += 1;
+    }
+    if pos == key_start {
+        return Err(ParseError::new(
+            line,
+            pos + 1,
+            format!("无效的配置项名称起始字符 '{}'", chars[pos]),
+        ));
+    }
+    let key: String = chars[key_start..pos].iter().collect();
+
+    pos = skip_whitespace(&chars, pos);
+    match chars.get(pos) {
+        Some('=') => pos += 1,
+        Some(c) => {
+            return Err(ParseError::new(
+                line,
+                pos + 1,
+                format!("{key} 后应为 '='，实际为 '{c}'"),
+            ));
+        }
+        None => return Err(ParseError::new(line, pos + 1, format!("{key} 后缺少 '='"))),
+    }
+
+    pos = skip_whitespace(&chars, pos);
+    let value_column = pos + 1;
+    let (value, value_end) = if chars.get(pos) == Some(&'"') {
+        parse_quoted(&chars, pos, line)?
+    } else {
+        parse_bare(&chars, pos)
+    };
+
+    let entry = Entry {
+        key,
+        value,
+        line,
+        key_column: key_start + 1,
+        value_column,
+        section: None,
+    };
+    Ok(Some((entry, value_end)))
+}
+
+const fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+fn skip_whitespace(chars: &[char], mut pos: usize) -> usize {
+    while pos < chars.len() && chars[pos].is_whitespace() {
+        pos += 1;
+    }
+    pos
+}
+
+/// 未加引号的值: 到行尾或空白后的 `#` 为止，去除首尾空白
+fn parse_bare(chars: &[char], start: usize) -> (String, usize) {
+    let mut end = chars.len();
+    for i in start..chars.len() {
+        if chars[i] == '#' && (i == start || chars[i - 1].is_whitespace()) {
+            end = i;
+            break;
+        }
+    }
+    while end > start && chars[end - 1].is_whitespace() {
+        end -= 1;
+    }
+    (chars[start..end].iter().collect(), end)
+}
+
+/// 双引号包裹的值，`start` 指向起始引号
+///
+/// 只解释 `\"`、`\\`、`\n` 与 `\t`，其余反斜杠按原样保留，便于直接书写 Windows 路径。
+fn parse_quoted(chars: &[char], start: usize, line: usize) -> Result<(String, usize), ParseError> {
+    let mut value = String::new();
+    let mut pos = start + 1;
+    loop {
+        match chars.get(pos) {
+            None => return Err(ParseError::new(line, start + 1, "引号未闭合")),
+            Some('"') => break,
+            Some('\\') => {
+                let escaped = match chars.get(pos + 1) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some(_) => {
+                        value.push('\\');
+                        pos += 1;
+                        continue;
+                    }
+                    None => return Err(ParseError::new(line, start + 1, "引号未闭合")),
+                };
+                value.push(escaped);
+                pos += 2;
+            }
+            Some(c) => {
+                value.push(*c);
+                pos += 1;
+            }
+        }
+    }
+
+    // 闭合引号之后只允许空白与注释
+    let rest = skip_whitespace(chars, pos + 1);
+    if rest < chars.len() && chars[rest] != '#' {
+        return Err(ParseError::new(line, rest + 1, "引号后存在多余内容"));
+    }
+    Ok((value, pos + 1))
+}
+
+/// 将值写回配置文件时加引号并转义
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// 未加引号时无法原样写回的值
+fn needs_quote(value: &str) -> bool {
+    value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '#' || c == '"')
+}
+
+/// 写入配置项时的值格式: 布尔值与数字不加引号，其余加引号
+pub fn format_value(value: &str) -> String {
+    let plain = value == "true" || value == "false" || value.parse::<f64>().is_ok();
+    if plain && !value.is_empty() {
+        value.to_string()
+    } else {
+        quote(value)
+    }
+}
+
+/// 保留注释、空行与原有顺序的配置文件，用于原地修改配置项
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    lines: Vec<String>,
+}
+
+impl Document {
+    /// 解析配置文件，语法错误时返回错误
+    pub fn parse(content: &str) -> Result<Self, ParseError> {
+        parse(content)?;
+        Ok(Self {
+            lines: content.lines().map(str::to_string).collect(),
+        })
+    }
+
+    /// 第一个配置块标题所在的行号，之前的行为顶层配置
+    fn top_level_end(&self) -> usize {
+        self.lines
+            .iter()
+            .position(|raw| raw.trim_start().starts_with('['))
+            .unwrap_or(self.lines.len())
+    }
+
+    /// 指定顶层配置项所在的行号 (从 0 开始)、配置项与值的结束位置
+    fn find(&self, key: &str) -> Vec<(usize, Entry, usize)> {
+        self.lines[..self.top_level_end()]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, raw)| {
+                let (entry, end) = parse_line(raw, index + 1).ok()??;
+                (entry.key == key).then_some((index, entry, end))
+            })
+            .collect()
+    }
+
+    /// 被注释掉的示例行，如 `# ws_server = "ws://..."`
+    fn find_commented(&self, key: &str) -> Option<usize> {
+        self.lines[..self.top_level_end()].iter().rposition(|raw| {
+            let Some(rest) = raw.trim_start().strip_prefix('#') else {
+                return false;
+            };
+            let rest = rest.trim_start_matches('#');
+            matches!(parse_line(rest, 0), Ok(Some((entry, _))) if entry.key == key)
+        })
+    }
+
+    /// 设置配置项的值，可重复的配置项传入多个值
+    ///
+    /// 已有的行只替换值本身，保留缩进、引号风格与行内注释；多余的旧行被删除。
+    /// 配置项不存在时写在被注释掉的示例之后，否则追加到顶层配置的末尾。
+    /// 只修改顶层配置项，配置块保持不变。
+    pub fn set(&mut self, key: &str, values: &[String]) {
+        let found = self.find(key);
+        for ((index, entry, end), value) in found.iter().zip(values) {
+            let chars: Vec<char> = self.lines[*index].chars().collect();
+            let start = entry.value_column - 1;
+            let was_quoted = chars.get(start) == Some(&'"');
+            let formatted = if was_quoted || needs_quote(value) {
+                quote(value)
+            } else {
+                value.clone()
+            };
+            let mut line: String = chars[..start].iter().collect();
+            line.push_str(&formatted);
+            line.extend(&chars[*end..]);
+            self.lines[*index] = line;
+        }
+
+        if values.len() > found.len() {
+            let at = match found.last() {
+                Some((index, _, _)) => index + 1,
+                None => self
+                    .find_commented(key)
+                    .map_or_else(|| self.append_position(), |i| i + 1),
+            };
+            let new_lines = values[found.len()..]
+                .iter()
+                .map(|value| format!("{key} = {}", format_value(value)));
+            self.lines.splice(at..at, new_lines);
+        } else {
+            for (index, _, _) in found[values.len()..].iter().rev() {
+                self.lines.remove(*index);
+            }
+        }
+    }
+
+    /// 新增顶层配置项的位置: 没有配置块时为文件末尾，否则为最后一个顶层配置项之后
+    fn append_position(&self) -> usize {
+        let end = self.top_level_end();
+        if end == self.lines.len() {
+            return end;
+        }
+        self.lines[..end]
+            .iter()
+            .rposition(|raw| matches!(parse_line(raw, 0), Ok(Some(_))))
+            .map_or(0, |i| i + 1)
+    }
+
+    /// 删除配置项的全部行
+    pub fn remove(&mut self, key: &str) {
+        self.set(key, &[]);
+    }
+
+    /// 生成配置文件内容
+    pub fn render(&self) -> String {
+        let mut content = self.lines.join("\n");
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, value: &str, line: usize, columns: (usize, usize)) -> Entry {
+        Entry {
+            key: key.to_string(),
+            value: value.to_string(),
+            line,
+            key_column: columns.0,
+            value_column: columns.1,
+            section: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let content = "\
+# 注释
+http_server = \"http://a:1\"   # 行内注释
+token=abc#def
+  fake = 2.5 # 倍率
+
+terminal_entry = \"say \\\"hi\\\" # not a comment\"
+empty = \"\"
+bare_empty =
+";
+        assert_eq!(
+            parse(content).unwrap(),
+            vec![
+                entry("http_server", "http://a:1", 2, (1, 15)),
+                entry("token", "abc#def", 3, (1, 7)),
+                entry("fake", "2.5", 4, (3, 10)),
+                entry("terminal_entry", "say \"hi\" # not a comment", 6, (1, 18)),
+                entry("empty", "", 7, (1, 9)),
+                entry("bare_empty", "", 8, (1, 13)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |content: &str| parse(content).unwrap_err();
+        assert_eq!(
+            error("ok = 1\ntoken \"abc\""),
+            ParseError::new(2, 7, "token 后应为 '='，实际为 '\"'")
+        );
+        assert_eq!(error("token"), ParseError::new(1, 6, "token 后缺少 '='"));
+        assert_eq!(
+            error("= 1"),
+            ParseError::new(1, 1, "无效的配置项名称起始字符 '='")
+        );
+        assert_eq!(error("token = \"abc"), ParseError::new(1, 9, "引号未闭合"));
+        assert_eq!(
+            error("token = \"a\" b"),
+            ParseError::new(1, 13, "引号后存在多余内容")
+        );
+        assert_eq!(
+            error("token = \"a\" b").to_string(),
+            "第 1 行第 13 列: 引号后存在多余内容"
+        );
+    }
+
+    #[test]
+    fn test_document_preserves_layout() {
+        let content = "\
+# 主端地址
+http_server = \"http://a\"   # 行内注释
+  fake = 1 # 倍率
+unknown_key = x
+# ws_server = \"ws://example\"
+
+quota_action = \"90 log\"
+quota_action = \"100 log\"
+";
+        let mut document = Document::parse(content).unwrap();
+        document.set("http_server", &["http://b #1".to_string()]);
+        document.set("fake", &["2.5".to_string()]);
+        document.set("ws_server", &["ws://b".to_string()]);
+        document.set("quota_action", &["95 log".to_string()]);
+        document.set("log_level", &["debug".to_string()]);
+        assert_eq!(
+            document.render(),
+            "\
+# 主端地址
+http_server = \"http://b #1\"   # 行内注释
+  fake = 2.5 # 倍率
+unknown_key = x
+# ws_server = \"ws://example\"
+ws_server = \"ws://b\"
+
+quota_action = \"95 log\"
+log_level = \"debug\"
+"
+        );
+
+        document.remove("ws_server");
+        document.set("fake", &["a b".to_string()]);
+        let entries = parse(&document.render()).unwrap();
+        assert_eq!(entries[1].value, "a b");
+        assert!(entries.iter().all(|entry| entry.key != "ws_server"));
+    }
+
+    #[test]
+    fn test_sections() {
+        let content = "\
+token = a
+[server]   # 迁移中的新主端
+token = b
+[server]
+token = c
+";
+        let entries = parse(content).unwrap();
+        let sections: Vec<_> = entries
+            .iter()
+            .map(|entry| entry.section.as_ref().map(|s| (s.name.as_str(), s.line)))
+            .collect();
+        assert_eq!(sections, [None, Some(("server", 2)), Some(("server", 4))]);
+
+        assert_eq!(
+            parse("[server").unwrap_err(),
+            ParseError::new(1, 8, "配置块标题缺少 ']'")
+        );
+        assert_eq!(
+            parse("[a b]").unwrap_err(),
+            ParseError::new(1, 3, "配置块名称中存在无效字符 ' '")
+        );
+
+        // 修改与新增的配置项只作用于顶层
+        let mut document = Document::parse(content).unwrap();
+        document.set("token", &["x".to_string()]);
+        document.set("fake", &["2".to_string()]);
+        assert_eq!(
+            document.render(),
+            "token = x\nfake = 2\n[server]   # 迁移中的新主端\ntoken = b\n[server]\ntoken = c\n"
+        );
+    }
+
+    #[test]
+    fn test_quote_roundtrip() {
+        let value = "a \"b\" \\ c # d";
+        let line = format!("key = {}", quote(value));
+        assert_eq!(parse(&line).unwrap()[0].value, value);
+    }
+
+    #[test]
+    fn test_unknown_escape_kept() {
+        let value = |content: &str| parse(content).unwrap()[0].value.clone();
+        assert_eq!(
+            value("ca_file = \"C:\\certs\\ca.pem\""),
+            "C:\\certs\\ca.pem"
+        );
+        assert_eq!(value("a = \"x\\qy\\\\z\\n\""), "x\\qy\\z\n");
+    }
+}
@@ -1,9 +1,10 @@
-use crate::config::IpProvider;
+use crate::config::Config;
 
 use crate::get_info::cpu::{arch, cpu_info_without_usage, realtime_cpu};
 use crate::get_info::ip::ip;
 use crate::get_info::load::realtime_load;
 use crate::get_info::mem::{mem_info_without_usage, realtime_disk, realtime_mem, realtime_swap};
+use crate::get_info::network::filter::InterfaceFilter;
 use crate::get_info::network::traffic_stats::TrafficStats;
 use crate::get_info::network::{realtime_connections, realtime_network};
 use crate::get_info::os::os;
@@ -33,10 +34,11 @@ pub struct BasicInfo {
 }
 
 impl BasicInfo {
-    pub async fn build(sysinfo_sys: &sysinfo::System, fake: f64, ip_provider: &IpProvider) -> Self {
+    pub async fn build(sysinfo_sys: &sysinfo::System, config: &Config) -> Self {
+        let fake = config.fake;
         let cpu = cpu_info_without_usage(sysinfo_sys);
         let mem_disk = mem_info_without_usage(sysinfo_sys);
-        let (ip, os) = tokio::join!(ip(ip_provider), os());
+        let (ip, os) = tokio::join!(ip(config), os());
 
         let fake_cpu_cores = (f64::from(cpu.cores) * fake) as u64;
         let fake_disk_total = (mem_disk.disk as f64 * fake) as u64;
@@ -64,12 +66,18 @@ impl BasicInfo {
         basic_info
     }
 
-    pub fn push(&self, basic_info_url: String, ignore_unsafe_cert: bool) {
+    /// 推送 Basic Info，返回是否成功
+    pub fn push(&self, basic_info_url: String, config: &Config) -> bool {
         let json_string = miniserde::json::to_string(self);
         #[cfg(feature = "ureq-support")]
         {
-            use crate::utils::create_ureq_agent;
-            let agent = create_ureq_agent(ignore_unsafe_cert);
+            let agent = match crate::utils::panel_ureq_agent(config) {
+                Ok(agent) => agent,
+                Err(e) => {
+                    error!("推送 Basic Info 错误: {e}");
+                    return false;
+                }
+            };
             let resp = agent
                 .post(basic_info_url)
                 .header("User-Agent", "curl/11.45.14-rs")
@@ -79,21 +87,28 @@ impl BasicInfo {
                 Ok(resp) => resp,
                 Err(e) => {
                     error!("推送 Basic Info 错误: {e}");
-                    return;
+                    return false;
                 }
             };
 
             if resp.status().is_success() {
                 info!("推送 Basic Info 成功");
-            } else {
-                error!("推送 Basic Info 失败，HTTP 状态码: {}", resp.status());
+                return true;
             }
+            error!("推送 Basic Info 失败，HTTP 状态码: {}", resp.status());
+            false
         }
         #[cfg(feature = "nyquest-support")]
         {
             use nyquest::Body;
             use nyquest::Request;
-            let client = crate::utils::create_nyquest_client(ignore_unsafe_cert);
+            let client = match crate::utils::panel_nyquest_client(config) {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("推送 Basic Info 错误: {e}");
+                    return false;
+                }
+            };
             let body = Body::text(json_string, "application/json");
             let resp = client.request(Request::post(basic_info_url).with_body(body));
 
@@ -101,15 +116,16 @@ impl BasicInfo {
                 Ok(resp) => resp,
                 Err(e) => {
                     error!("推送 Basic Info 错误: {e}");
-                    return;
+                    return false;
                 }
             };
 
             if resp.status().is_successful() {
                 info!("推送 Basic Info 成功");
-            } else {
-                error!("推送 Basic Info 失败，HTTP 状态码: {}", resp.status());
+                return true;
             }
+            error!("推送 Basic Info 失败，HTTP 状态码: {}", resp.status());
+            false
         }
     }
 }
@@ -176,6 +192,7 @@ impl RealTimeInfo {
     pub fn build(
         sysinfo_sys: &sysinfo::System,
         network: &Networks,
+        interface_filter: &InterfaceFilter,
         disk: &Disks,
         traffic_stats: &mut TrafficStats,
         duration_ms: u64,
@@ -197,7 +214,7 @@ impl RealTimeInfo {
         let fake_load5 = load.load5 * fake;
         let fake_load15 = load.load15 * fake;
 
-        let network_info = realtime_network(network, traffic_stats, duration_ms);
+        let network_info = realtime_network(network, interface_filter, traffic_stats, duration_ms);
         let fake_network_up = (network_info.up as f64 * fake) as u64;
         let fake_network_down = (network_info.down as f64 * fake) as u64;
         let fake_network_total_up = (network_info.total_up as f64 * fake) as u64;
@@ -1,5 +1,5 @@
-use crate::config::IpProvider;
-use log::trace;
+use crate::config::{Config, IpProvider};
+use log::{trace, warn};
 use miniserde::{Deserialize, Serialize, json};
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
@@ -8,13 +8,44 @@ use tokio::task::JoinHandle;
 #[cfg(feature = "ureq-support")]
 use std::time::Duration;
 
-pub async fn ip(provider: &IpProvider) -> IPInfo {
-    match provider {
-        IpProvider::Cloudflare => ip_cloudflare().await,
-        IpProvider::Ipinfo => ip_ipinfo().await,
+/// 查询 IP 使用的 HTTP 客户端，与主端连接使用同样的代理 (经由代理时查询到的是代理的出口 IP)
+#[cfg(feature = "ureq-support")]
+type Agent = ureq::Agent;
+#[cfg(not(feature = "ureq-support"))]
+type Agent = ();
+
+pub async fn ip(config: &Config) -> IPInfo {
+    #[cfg(feature = "ureq-support")]
+    let agent = match ip_agent(config) {
+        Ok(agent) => agent,
+        Err(e) => {
+            warn!("无法查询 IP: {e}");
+            return IPInfo {
+                ipv4: None,
+                ipv6: None,
+            };
+        }
+    };
+    #[cfg(not(feature = "ureq-support"))]
+    let agent = ();
+
+    match config.ip_provider {
+        IpProvider::Cloudflare => ip_cloudflare(&agent).await,
+        IpProvider::Ipinfo => ip_ipinfo(&agent).await,
     }
 }
 
+#[cfg(feature = "ureq-support")]
+fn ip_agent(config: &Config) -> Result<ureq::Agent, String> {
+    let tls = crate::rustls_config::general_tls_config(config)?;
+    let proxy = crate::proxy::ProxySettings::from_config(config);
+    Ok(crate::rustls_config::ureq_agent(
+        tls,
+        proxy,
+        Some(Duration::from_secs(5)),
+    ))
+}
+
 #[derive(Debug)]
 pub struct IPInfo {
     pub ipv4: Option<Ipv4Addr>,
@@ -26,11 +57,16 @@ struct IpJson {
     ip: String,
 }
 
-pub async fn ip_ipinfo() -> IPInfo {
+#[cfg_attr(not(feature = "ureq-support"), allow(unused_variables))]
+pub async fn ip_ipinfo(agent: &Agent) -> IPInfo {
+    #[cfg(feature = "ureq-support")]
+    let (agent_v4, agent_v6) = (agent.clone(), agent.clone());
+
     let ipv4: JoinHandle<Option<Ipv4Addr>> = tokio::spawn(async move {
         #[cfg(feature = "ureq-support")]
         let body = {
-            let Ok(mut resp) = ureq::get("https://ipinfo.io")
+            let Ok(mut resp) = agent_v4
+                .get("https://ipinfo.io")
                 .header("User-Agent", "curl/8.7.1")
                 .config()
                 .timeout_global(Some(Duration::from_secs(5)))
@@ -74,7 +110,8 @@ pub async fn ip_ipinfo() -> IPInfo {
     let ipv6: JoinHandle<Option<Ipv6Addr>> = tokio::spawn(async move {
         #[cfg(feature = "ureq-support")]
         let body = {
-            let Ok(mut resp) = ureq::get("https://6.ipinfo.io")
+            let Ok(mut resp) = agent_v6
+                .get("https://6.ipinfo.io")
                 .header("User-Agent", "curl/8.7.1")
                 .config()
                 .timeout_global(Some(Duration::from_secs(5)))
@@ -124,11 +161,16 @@ pub async fn ip_ipinfo() -> IPInfo {
     ip_info
 }
 
-pub async fn ip_cloudflare() -> IPInfo {
+#[cfg_attr(not(feature = "ureq-support"), allow(unused_variables))]
+pub async fn ip_cloudflare(agent: &Agent) -> IPInfo {
+    #[cfg(feature = "ureq-support")]
+    let (agent_v4, agent_v6) = (agent.clone(), agent.clone());
+
     let ipv4: JoinHandle<Option<Ipv4Addr>> = tokio::spawn(async move {
         #[cfg(feature = "ureq-support")]
         let body = {
-            let Ok(mut resp) = ureq::get("https://www.cloudflare.com/cdn-cgi/trace")
+            let Ok(mut resp) = agent_v4
+                .get("https://www.cloudflare.com/cdn-cgi/trace")
                 .header("User-Agent", "curl/8.7.1")
                 .config()
                 .timeout_global(Some(Duration::from_secs(5)))
@@ -173,7 +215,8 @@ pub async fn ip_cloudflare() -> IPInfo {
     let ipv6: JoinHandle<Option<Ipv6Addr>> = tokio::spawn(async move {
         #[cfg(feature = "ureq-support")]
         let body = {
-            let Ok(mut resp) = ureq::get("https://www.cloudflare.com/cdn-cgi/trace")
+            let Ok(mut resp) = agent_v6
+                .get("https://www.cloudflare.com/cdn-cgi/trace")
                 .header("User-Agent", "curl/8.7.1")
                 .config()
                 .timeout_global(Some(Duration::from_secs(5)))
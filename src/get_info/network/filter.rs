@@ -0,0 +1,113 @@
+use crate::config::Config;
+
+/// 默认排除的网卡 (回环、容器、虚拟网桥等)
+pub const DEFAULT_INTERFACE_EXCLUDE: [&str; 14] = [
+    "lo", "lo0", "docker*", "br-*", "veth*", "virbr*", "vmbr*", "cni*", "flannel*", "podman*",
+    "tap*", "tun*", "fwln*", "fwpr*",
+];
+
+/// 参与流量统计的网卡筛选规则
+///
+/// 设置了 `primary_interfaces` 时只统计其中的网卡；否则统计匹配 `interface_include`
+/// (为空表示全部) 且不匹配 `interface_exclude` 的网卡。规则均为 glob，支持 `*` 与 `?`。
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    primary: Vec<String>,
+}
+
+impl InterfaceFilter {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            include: config.interface_include.clone(),
+            exclude: config.interface_exclude.clone(),
+            primary: config.primary_interfaces.clone(),
+        }
+    }
+
+    /// 该网卡是否参与流量统计
+    pub fn is_counted(&self, name: &str) -> bool {
+        if !self.primary.is_empty() {
+            return self.primary.iter().any(|p| glob_match(p, name));
+        }
+        (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, name)))
+            && !self.exclude.iter().any(|p| glob_match(p, name))
+    }
+}
+
+/// glob 匹配，`*` 匹配任意长度字符，`?` 匹配单个字符
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // 最近一个 `*` 的位置及其当时对应的 name 位置，用于回溯
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], primary: &[&str]) -> InterfaceFilter {
+        let to_vec = |v: &[&str]| v.iter().map(ToString::to_string).collect();
+        InterfaceFilter {
+            include: to_vec(include),
+            exclude: to_vec(exclude),
+            primary: to_vec(primary),
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("eth*", "eth0"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("enp?s*", "enp1s0"));
+        assert!(glob_match("*tun*", "wg-tun0"));
+        assert!(!glob_match("tun*", "wg-tun0"));
+        assert!(!glob_match("lo", "wlo1"));
+        assert!(!glob_match("eth?", "eth10"));
+    }
+
+    #[test]
+    fn test_default_exclude() {
+        let default = filter(&[], &DEFAULT_INTERFACE_EXCLUDE, &[]);
+        for name in ["eth0", "wlo1", "wg-tun0", "enp3s0", "ppp0", "wg0"] {
+            assert!(default.is_counted(name), "{name} 应参与统计");
+        }
+        for name in ["lo", "docker0", "br-1a2b3c", "veth12ab", "virbr0", "tun0"] {
+            assert!(!default.is_counted(name), "{name} 不应参与统计");
+        }
+    }
+
+    #[test]
+    fn test_include_and_primary() {
+        let include = filter(&["eth*", "wg*"], &["eth1"], &[]);
+        assert!(include.is_counted("eth0"));
+        assert!(include.is_counted("wg0"));
+        assert!(!include.is_counted("eth1"));
+        assert!(!include.is_counted("wlan0"));
+
+        // primary_interfaces 优先于 include/exclude
+        let primary = filter(&["eth*"], &["eth0"], &["eth0"]);
+        assert!(primary.is_counted("eth0"));
+        assert!(!primary.is_counted("eth1"));
+    }
+}
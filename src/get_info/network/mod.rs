@@ -1,66 +1,47 @@
 use crate::data_struct::{Connections, Network};
+use crate::get_info::network::filter::InterfaceFilter;
+use crate::get_info::network::traffic_stats::InterfaceCounter;
 use log::trace;
 use sysinfo::Networks;
+pub mod filter;
 #[cfg(target_os = "linux")]
 mod netlink;
+pub mod traffic_control;
+pub mod traffic_history;
 pub mod traffic_stats;
 
-/// 获取系统总流量（用于计费周期统计）
-pub fn get_system_total_traffic(network: &Networks) -> (u64, u64) {
-    let mut total_up = 0;
-    let mut total_down = 0;
-
-    for (name, data) in network {
-        if is_virtual_interface(name) {
-            continue;
-        }
-        total_up += data.total_transmitted();
-        total_down += data.total_received();
-    }
-
-    (total_up, total_down)
-}
-
-/// 检查是否为虚拟网络接口
-#[inline]
-fn is_virtual_interface(name: &str) -> bool {
-    name.contains("br")
-        || name.contains("cni")
-        || name.contains("docker")
-        || name.contains("podman")
-        || name.contains("flannel")
-        || name.contains("lo")
-        || name.contains("veth")
-        || name.contains("virbr")
-        || name.contains("vmbr")
-        || name.contains("tap")
-        || name.contains("tun")
-        || name.contains("fwln")
-        || name.contains("fwpr")
+/// 获取参与统计的各网卡的系统累计流量（用于计费周期统计）
+pub fn interface_counters(network: &Networks, filter: &InterfaceFilter) -> Vec<InterfaceCounter> {
+    network
+        .iter()
+        .filter(|(name, _)| filter.is_counted(name))
+        .map(|(name, data)| InterfaceCounter {
+            name: name.clone(),
+            total_up: data.total_transmitted(),
+            total_down: data.total_received(),
+        })
+        .collect()
 }
 
 pub fn realtime_network(
     network: &Networks,
+    filter: &InterfaceFilter,
     traffic_stats: &mut traffic_stats::TrafficStats,
     duration_ms: u64,
 ) -> Network {
-    let mut total_up = 0;
-    let mut total_down = 0;
     let mut up = 0;
     let mut down = 0;
 
     for (name, data) in network {
-        if is_virtual_interface(name) {
+        if !filter.is_counted(name) {
             continue;
         }
-        total_up += data.total_transmitted();
-        total_down += data.total_received();
         up += data.transmitted();
         down += data.received();
     }
 
     // 更新计费周期的累计流量
-    let (cycle_up, cycle_down) = traffic_stats.update(total_up, total_down);
+    let (cycle_up, cycle_down) = traffic_stats.update(&interface_counters(network, filter));
 
     let duration_secs = duration_ms as f64 / 1000.0;
     let network_info = Network {
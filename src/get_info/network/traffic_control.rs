@@ -0,0 +1,471 @@
+use crate::config::Config;
+use crate::get_info::network::traffic_stats::{TrafficStats, format_bytes};
+use crate::quota::prune_fired_thresholds;
+use crate::storage::{instance_data_dir, instance_dir, write_atomic};
+use miniserde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// 运行中的 Agent 持有该文件的排他锁
+const LOCK_FILE: &str = "agent.lock";
+/// 命令行向运行中的 Agent 提交流量调整的控制文件
+const CONTROL_FILE: &str = "traffic_control.cmd";
+
+/// 对本周期流量的手动调整
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrafficAdjustment {
+    /// 清零本周期流量 (含各网卡流量)
+    Reset,
+    /// 将本周期流量设为指定值，未指定的方向保持不变
+    ///
+    /// 只校正计费总量，各网卡流量仍为实际统计值，无法得知校正量应归属哪块网卡。
+    Set { up: Option<u64>, down: Option<u64> },
+    /// 在本周期流量上增加偏移 (可为负)，同样不改变各网卡流量
+    Add { up: i64, down: i64 },
+}
+
+impl TrafficAdjustment {
+    /// 控制文件中的单行表示
+    fn to_line(&self) -> String {
+        let opt = |v: Option<u64>| v.map_or_else(|| "-".to_string(), |v| v.to_string());
+        match self {
+            Self::Reset => "reset".to_string(),
+            Self::Set { up, down } => format!("set {} {}", opt(*up), opt(*down)),
+            Self::Add { up, down } => format!("add {up} {down}"),
+        }
+    }
+
+    fn parse_line(line: &str) -> Option<Self> {
+        let opt = |v: &str| -> Option<Option<u64>> {
+            if v == "-" {
+                Some(None)
+            } else {
+                v.parse().ok().map(Some)
+            }
+        };
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts[..] {
+            ["reset"] => Some(Self::Reset),
+            ["set", up, down] => Some(Self::Set {
+                up: opt(up)?,
+                down: opt(down)?,
+            }),
+            ["add", up, down] => Some(Self::Add {
+                up: up.parse().ok()?,
+                down: down.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// 应用到统计数据，当天流量的起点随之平移，保证每日流量不受影响
+    pub fn apply(&self, stats: &mut TrafficStats) {
+        let (old_up, old_down) = (stats.cycle_up, stats.cycle_down);
+        match *self {
+            Self::Reset => {
+                stats.cycle_up = 0;
+                stats.cycle_down = 0;
+                stats.day_start_up = 0;
+                stats.day_start_down = 0;
+                stats.fired_thresholds.clear();
+                for iface in stats.interfaces.values_mut() {
+                    iface.cycle_up = 0;
+                    iface.cycle_down = 0;
+                }
+                return;
+            }
+            Self::Set { up, down } => {
+                stats.cycle_up = up.unwrap_or(stats.cycle_up);
+                stats.cycle_down = down.unwrap_or(stats.cycle_down);
+            }
+            Self::Add { up, down } => {
+                stats.cycle_up = stats.cycle_up.saturating_add_signed(up);
+                stats.cycle_down = stats.cycle_down.saturating_add_signed(down);
+            }
+        }
+        stats.day_start_up = shift(stats.day_start_up, old_up, stats.cycle_up);
+        stats.day_start_down = shift(stats.day_start_down, old_down, stats.cycle_down);
+    }
+}
+
+impl Display for TrafficAdjustment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let signed = |v: i64| {
+            let sign = if v < 0 { "-" } else { "+" };
+            format!("{sign}{}", format_bytes(v.unsigned_abs()))
+        };
+        match self {
+            Self::Reset => write!(f, "清零"),
+            Self::Set { up, down } => {
+                write!(f, "设为")?;
+                if let Some(up) = up {
+                    write!(f, " 上行 {}", format_bytes(*up))?;
+                }
+                if let Some(down) = down {
+                    write!(f, " 下行 {}", format_bytes(*down))?;
+                }
+                Ok(())
+            }
+            Self::Add { up, down } => {
+                write!(f, "上行 {}, 下行 {}", signed(*up), signed(*down))
+            }
+        }
+    }
+}
+
+/// 应用调整并移除不再达到的配额阈值
+pub fn apply_adjustment(stats: &mut TrafficStats, adjustment: &TrafficAdjustment, config: &Config) {
+    adjustment.apply(stats);
+    let usage = stats.usage(config.traffic_billing_mode, config.traffic_quota);
+    prune_fired_thresholds(stats, usage);
+}
+
+/// 将 `value` 按 `from -> to` 的变化量平移，不超过 `to`
+fn shift(value: u64, from: u64, to: u64) -> u64 {
+    let shifted = if to >= from {
+        value.saturating_add(to - from)
+    } else {
+        value.saturating_sub(from - to)
+    };
+    shifted.min(to)
+}
+
+/// 解析流量大小，支持 K/M/G/T 后缀 (1024 进制)，如 `1073741824`、`500M`、`1.5G`
+///
+/// 单位至多一个，形如 `G`、`GB`、`GiB` 或 `B`，不区分大小写。
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("无效的流量大小: {value}");
+    let upper = value.trim().to_ascii_uppercase();
+    let rest = upper.strip_suffix('B').unwrap_or(&upper);
+    let (rest, binary) = rest
+        .strip_suffix('I')
+        .map_or((rest, false), |rest| (rest, true));
+    let (number, multiplier): (&str, u64) = match rest.chars().last() {
+        Some('K') => (&rest[..rest.len() - 1], 1 << 10),
+        Some('M') => (&rest[..rest.len() - 1], 1 << 20),
+        Some('G') => (&rest[..rest.len() - 1], 1 << 30),
+        Some('T') => (&rest[..rest.len() - 1], 1 << 40),
+        _ if binary => return Err(invalid()),
+        _ => (rest, 1),
+    };
+
+    if let Ok(bytes) = number.parse::<u64>() {
+        return bytes.checked_mul(multiplier).ok_or_else(invalid);
+    }
+    let bytes: f64 = number.parse().map_err(|_| invalid())?;
+    if !bytes.is_finite() || bytes < 0.0 {
+        return Err(invalid());
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let total = (bytes * multiplier as f64).round();
+    if total >= u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(total as u64)
+}
+
+/// 解析带符号的流量偏移，如 `+1G`、`-500M`
+pub fn parse_offset(value: &str) -> Result<i64, String> {
+    let (negative, size) = match value.trim().strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.trim().trim_start_matches('+')),
+    };
+    let size = i64::try_from(parse_size(size)?).map_err(|_| format!("流量偏移过大: {value}"))?;
+    Ok(if negative { -size } else { size })
+}
+
+fn open_lock_file(data_dir: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(data_dir.join(LOCK_FILE))
+}
+
+/// Agent 启动时锁定数据目录，返回的文件需保持打开直到退出
+///
+/// 同一数据目录已有 Agent 运行时返回错误，避免两个进程同时写入状态文件。
+pub fn lock_data_dir(data_dir: &Path) -> Result<File, String> {
+    let file = open_lock_file(data_dir).map_err(|e| format!("无法创建锁文件: {e}"))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(format!(
+            "数据目录 {} 已被另一个 Agent 使用",
+            data_dir.display()
+        )),
+        Err(TryLockError::Error(e)) => Err(format!("无法锁定数据目录: {e}")),
+    }
+}
+
+/// 取出命令行提交的流量调整 (由运行中的 Agent 调用)
+pub fn take_pending(data_dir: &Path) -> Option<TrafficAdjustment> {
+    let path = data_dir.join(CONTROL_FILE);
+    if !path.exists() {
+        return None;
+    }
+    // 先改名再读取，命令行据此判断请求是否已被接收
+    let taken = path.with_extension("taken");
+    fs::rename(&path, &taken).ok()?;
+    let content = fs::read_to_string(&taken).unwrap_or_default();
+    let _ = fs::remove_file(&taken);
+    let adjustment = TrafficAdjustment::parse_line(content.trim());
+    if adjustment.is_none() {
+        log::warn!("忽略无法识别的流量调整请求: {}", content.trim());
+    }
+    adjustment
+}
+
+#[derive(Serialize)]
+struct TrafficSummary {
+    cycle_start: String,
+    billing_mode: String,
+    up: u64,
+    down: u64,
+    billed: u64,
+    quota: u64,
+    remaining: Option<u64>,
+    percent_used: Option<f64>,
+    today_up: u64,
+    today_down: u64,
+    fired_thresholds: Vec<u32>,
+    agent_running: bool,
+}
+
+/// Agent 是否正在运行 (数据目录已被锁定)，不会创建锁文件
+pub fn agent_running(data_dir: &Path) -> bool {
+    File::open(data_dir.join(LOCK_FILE))
+        .is_ok_and(|file| matches!(file.try_lock(), Err(TryLockError::WouldBlock)))
+}
+
+fn read_stats(data_dir: &Path) -> Result<TrafficStats, String> {
+    TrafficStats::read(data_dir)
+        .ok_or_else(|| format!("{} 下没有可用的流量统计", data_dir.display()))
+}
+
+/// `traffic show`: 输出本周期流量
+pub fn show(config: &Config, json: bool) -> Result<(), String> {
+    let data_dir = instance_dir(config);
+    let stats = read_stats(&data_dir)?;
+    let usage = stats.usage(config.traffic_billing_mode, config.traffic_quota);
+    let cycle = stats.current_cycle_record();
+    let today = stats.current_day_record();
+
+    let summary = TrafficSummary {
+        cycle_start: cycle.date,
+        billing_mode: config.traffic_billing_mode.as_str().to_string(),
+        up: stats.cycle_up,
+        down: stats.cycle_down,
+        billed: usage.billed,
+        quota: usage.quota,
+        remaining: usage.remaining(),
+        percent_used: usage.percent_used(),
+        today_up: today.as_ref().map_or(0, |t| t.up),
+        today_down: today.as_ref().map_or(0, |t| t.down),
+        fired_thresholds: stats.fired_thresholds.clone(),
+        agent_running: agent_running(&data_dir),
+    };
+
+    if json {
+        println!("{}", miniserde::json::to_string(&summary));
+        return Ok(());
+    }
+
+    println!("计费周期: {} 起", summary.cycle_start);
+    println!("上行: {}", format_bytes(summary.up));
+    println!("下行: {}", format_bytes(summary.down));
+    println!("计费用量 ({}): {usage}", summary.billing_mode);
+    println!(
+        "今日: 上行 {}, 下行 {}",
+        format_bytes(summary.today_up),
+        format_bytes(summary.today_down)
+    );
+    if !stats.interfaces.is_empty() {
+        println!("各网卡: {}", stats.interface_summary());
+    }
+    if !summary.fired_thresholds.is_empty() {
+        let fired: Vec<String> = summary
+            .fired_thresholds
+            .iter()
+            .map(|t| format!("{t}%"))
+            .collect();
+        println!("已触发阈值: {}", fired.join(", "));
+    }
+    println!(
+        "Agent: {}",
+        if summary.agent_running {
+            "运行中"
+        } else {
+            "未运行"
+        }
+    );
+    Ok(())
+}
+
+/// `traffic reset/set/add`: 调整本周期流量
+///
+/// Agent 未运行时直接修改统计文件；运行中时通过控制文件提交，由 Agent 在下一次采样时应用，
+/// 避免双方同时写入统计文件。
+pub fn adjust(config: &Config, adjustment: &TrafficAdjustment) -> Result<(), String> {
+    let data_dir = instance_data_dir(config);
+    let lock = open_lock_file(&data_dir).map_err(|e| format!("无法打开锁文件: {e}"))?;
+
+    match lock.try_lock() {
+        Ok(()) => {
+            let mut stats = read_stats(&data_dir)?;
+            apply_adjustment(&mut stats, adjustment, config);
+            stats.save();
+            println!("已更新流量统计 (Agent 未运行)");
+        }
+        Err(TryLockError::WouldBlock) => {
+            submit(&data_dir, adjustment, config.realtime_info_interval)?;
+            println!("运行中的 Agent 已应用调整");
+        }
+        Err(TryLockError::Error(e)) => return Err(format!("无法锁定数据目录: {e}")),
+    }
+
+    let stats = read_stats(&data_dir)?;
+    println!(
+        "本周期: 上行 {}, 下行 {}",
+        format_bytes(stats.cycle_up),
+        format_bytes(stats.cycle_down)
+    );
+    Ok(())
+}
+
+/// 写入控制文件并等待运行中的 Agent 接收
+fn submit(data_dir: &Path, adjustment: &TrafficAdjustment, interval_ms: u64) -> Result<(), String> {
+    let path: PathBuf = data_dir.join(CONTROL_FILE);
+    if path.exists() {
+        return Err("已有尚未处理的流量调整请求，请稍后重试".to_string());
+    }
+    write_atomic(&path, format!("{}\n", adjustment.to_line()).as_bytes())
+        .map_err(|e| format!("无法写入控制文件: {e}"))?;
+
+    let limit = Duration::from_millis(interval_ms.saturating_mul(3)).max(Duration::from_secs(5));
+    let started = Instant::now();
+    while started.elapsed() < limit {
+        std::thread::sleep(Duration::from_millis(100));
+        if !path.exists() {
+            // 控制文件被接收后，Agent 会立即保存统计文件
+            std::thread::sleep(Duration::from_millis(200));
+            return Ok(());
+        }
+    }
+
+    // 超时后撤回请求；撤回失败说明 Agent 恰好已接收
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        _ => Err("Agent 未在规定时间内处理调整请求，已撤回".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_info::network::traffic_stats::InterfaceTraffic;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_size("2TiB"), Ok(2 << 40));
+        assert_eq!(parse_size("10kb"), Ok(10 << 10));
+        assert!(parse_size("-1G").is_err());
+        assert!(parse_size("1X").is_err());
+        assert_eq!(parse_size("3B"), Ok(3));
+        for invalid in ["1GM", "1BBB", "5IB", "1GIBB", "G", "1 G"] {
+            assert!(parse_size(invalid).is_err(), "{invalid}");
+        }
+        assert!(parse_size("").is_err());
+        assert_eq!(parse_offset("-500M"), Ok(-(500 << 20)));
+        assert_eq!(parse_offset("+1K"), Ok(1024));
+    }
+
+    #[test]
+    fn test_apply_adjustment() {
+        let mut stats = TrafficStats::default();
+        stats.interfaces.insert(
+            "eth0".to_string(),
+            InterfaceTraffic {
+                cycle_up: 700,
+                cycle_down: 800,
+                ..Default::default()
+            },
+        );
+        stats.cycle_up = 1000;
+        stats.cycle_down = 2000;
+        stats.day_start_up = 900;
+        stats.day_start_down = 1500;
+        stats.fired_thresholds = vec![90];
+
+        let set = TrafficAdjustment::Set {
+            up: Some(5000),
+            down: None,
+        };
+        assert_eq!(
+            TrafficAdjustment::parse_line(&set.to_line()),
+            Some(set.clone())
+        );
+        set.apply(&mut stats);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (5000, 2000));
+        // 当天流量保持不变
+        assert_eq!((stats.day_start_up, stats.day_start_down), (4900, 1500));
+
+        let add = TrafficAdjustment::Add {
+            up: -6000,
+            down: 500,
+        };
+        assert_eq!(
+            TrafficAdjustment::parse_line(&add.to_line()),
+            Some(add.clone())
+        );
+        add.apply(&mut stats);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (0, 2500));
+        assert_eq!((stats.day_start_up, stats.day_start_down), (0, 2000));
+        // set / add 只校正计费总量，各网卡流量保持实际统计值
+        assert_eq!(
+            (
+                stats.interfaces["eth0"].cycle_up,
+                stats.interfaces["eth0"].cycle_down
+            ),
+            (700, 800)
+        );
+
+        TrafficAdjustment::Reset.apply(&mut stats);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (0, 0));
+        assert!(stats.fired_thresholds.is_empty());
+        assert_eq!(
+            (
+                stats.interfaces["eth0"].cycle_up,
+                stats.interfaces["eth0"].cycle_down
+            ),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_control_file_handoff() {
+        let dir = std::env::temp_dir().join(format!("komari-control-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let lock = lock_data_dir(&dir).unwrap();
+        assert!(agent_running(&dir));
+        assert!(lock_data_dir(&dir).is_err());
+
+        assert_eq!(take_pending(&dir), None);
+        write_atomic(&dir.join(CONTROL_FILE), b"add 10 -20\n").unwrap();
+        assert_eq!(
+            take_pending(&dir),
+            Some(TrafficAdjustment::Add { up: 10, down: -20 })
+        );
+        assert!(!dir.join(CONTROL_FILE).exists());
+
+        drop(lock);
+        assert!(!agent_running(&dir));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
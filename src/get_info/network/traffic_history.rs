@@ -0,0 +1,252 @@
+use crate::config::Config;
+use crate::get_info::network::traffic_stats::{TrafficStats, format_bytes};
+use crate::storage::instance_dir;
+use miniserde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// 历史文件名，与统计文件位于同一目录
+pub const HISTORY_FILE: &str = "traffic_history.dat";
+
+/// 历史记录类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKind {
+    /// 已结束的计费周期
+    Cycle,
+    /// 已结束的自然日 (计费时区)
+    Day,
+}
+
+impl HistoryKind {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Cycle => "cycle",
+            Self::Day => "day",
+        }
+    }
+}
+
+/// 一条历史记录，文件中每行一条: `<cycle|day>\t<开始日期>\t<上行>\t<下行>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryRecord {
+    pub kind: HistoryKind,
+    /// 周期开始日期或当天日期 (YYYY-MM-DD)
+    pub date: String,
+    pub up: u64,
+    pub down: u64,
+}
+
+impl HistoryRecord {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let kind = match fields.next()? {
+            "cycle" => HistoryKind::Cycle,
+            "day" => HistoryKind::Day,
+            _ => return None,
+        };
+        let date = fields.next()?.to_string();
+        let up = fields.next()?.parse().ok()?;
+        let down = fields.next()?.parse().ok()?;
+        Some(Self {
+            kind,
+            date,
+            up,
+            down,
+        })
+    }
+}
+
+/// 追加一条历史记录
+pub fn append(path: &Path, record: &HistoryRecord) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+
+    // 上次写入被中断时文件末尾可能是半行，另起一行避免与新记录粘连
+    let len = file.metadata()?.len();
+    let mut prefix = "";
+    if len > 0 {
+        let mut last = [0u8; 1];
+        file.seek(SeekFrom::Start(len - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            prefix = "\n";
+        }
+    }
+
+    writeln!(
+        file,
+        "{prefix}{}\t{}\t{}\t{}",
+        record.kind.as_str(),
+        record.date,
+        record.up,
+        record.down
+    )?;
+    file.sync_data()
+}
+
+/// 读取全部历史记录，跳过无法解析的行 (如断电导致的半行)
+pub fn load(path: &Path) -> Vec<HistoryRecord> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(HistoryRecord::parse)
+        .collect()
+}
+
+/// `traffic history` 子命令的参数
+#[derive(Debug, Clone)]
+pub struct HistoryOptions {
+    /// 显示最近多少个计费周期
+    pub cycles: usize,
+    /// 显示最近多少天
+    pub days: usize,
+    /// 以 JSON 输出
+    pub json: bool,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        Self {
+            cycles: 12,
+            days: 31,
+            json: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HistoryEntry {
+    date: String,
+    up: u64,
+    down: u64,
+    billed: u64,
+    /// 是否为进行中的周期 / 当天
+    current: bool,
+}
+
+#[derive(Serialize)]
+struct HistoryOutput {
+    billing_mode: String,
+    cycles: Vec<HistoryEntry>,
+    days: Vec<HistoryEntry>,
+}
+
+/// 输出最近的计费周期与每日流量，便于核对服务商账单
+pub fn print_history(config: &Config, options: &HistoryOptions) {
+    let data_dir = instance_dir(config);
+    let records = load(&data_dir.join(HISTORY_FILE));
+    let stats = TrafficStats::read(&data_dir);
+    let mode = config.traffic_billing_mode;
+
+    let entry = |record: &HistoryRecord, current: bool| HistoryEntry {
+        date: record.date.clone(),
+        up: record.up,
+        down: record.down,
+        billed: mode.billed(record.up, record.down),
+        current,
+    };
+    let collect = |kind: HistoryKind, current: Option<HistoryRecord>, limit: usize| {
+        let mut entries: Vec<HistoryEntry> = records
+            .iter()
+            .filter(|r| r.kind == kind)
+            .map(|r| entry(r, false))
+            .chain(current.iter().map(|r| entry(r, true)))
+            .collect();
+        entries.drain(..entries.len().saturating_sub(limit));
+        entries
+    };
+
+    let output = HistoryOutput {
+        billing_mode: mode.as_str().to_string(),
+        cycles: collect(
+            HistoryKind::Cycle,
+            stats.as_ref().map(TrafficStats::current_cycle_record),
+            options.cycles,
+        ),
+        days: collect(
+            HistoryKind::Day,
+            stats.as_ref().and_then(TrafficStats::current_day_record),
+            options.days,
+        ),
+    };
+
+    if options.json {
+        println!("{}", miniserde::json::to_string(&output));
+        return;
+    }
+
+    println!("计费方式: {}", output.billing_mode);
+    print_table("计费周期", &output.cycles);
+    println!();
+    print_table("日期", &output.days);
+}
+
+/// 按终端显示宽度补齐 (中文字符占两列)
+fn pad(text: &str, width: usize, left: bool) -> String {
+    let len: usize = text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum();
+    let fill = " ".repeat(width.saturating_sub(len));
+    if left {
+        format!("{text}{fill}")
+    } else {
+        format!("{fill}{text}")
+    }
+}
+
+fn print_table(title: &str, entries: &[HistoryEntry]) {
+    println!(
+        "{}  {}  {}  {}",
+        pad(title, 12, true),
+        pad("上行", 12, false),
+        pad("下行", 12, false),
+        pad("计费用量", 12, false)
+    );
+    if entries.is_empty() {
+        println!("(暂无记录)");
+    }
+    for entry in entries {
+        println!(
+            "{}  {}  {}  {}{}",
+            pad(&entry.date, 12, true),
+            pad(&format_bytes(entry.up), 12, false),
+            pad(&format_bytes(entry.down), 12, false),
+            pad(&format_bytes(entry.billed), 12, false),
+            if entry.current { "  (进行中)" } else { "" }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_append_and_load() {
+        let path = std::env::temp_dir().join(format!("komari-history-{}.dat", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let cycle = HistoryRecord {
+            kind: HistoryKind::Cycle,
+            date: "2025-11-01".to_string(),
+            up: 100,
+            down: 200,
+        };
+        let day = HistoryRecord {
+            kind: HistoryKind::Day,
+            date: "2025-12-01".to_string(),
+            up: 1,
+            down: 2,
+        };
+        append(&path, &cycle).unwrap();
+        // 模拟写入一半时断电留下的残行
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"day\t2025-11-30\t5").unwrap();
+        append(&path, &day).unwrap();
+
+        assert_eq!(load(&path), vec![cycle, day]);
+        let _ = fs::remove_file(&path);
+    }
+}
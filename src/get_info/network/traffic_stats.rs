@@ -1,7 +1,137 @@
+use crate::get_info::network::traffic_history::{self, HISTORY_FILE, HistoryKind, HistoryRecord};
+use crate::storage::{crc32, write_atomic};
+use crate::timezone::BillingTimezone;
 use log::{debug, error, info, trace, warn};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Write};
 use std::fs;
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// 统计文件头的魔数
+const STATS_MAGIC: &str = "KOMARI-TRAFFIC-STATS";
+/// 当前统计文件格式版本 (旧版无文件头的 CSV 视为 v1)
+const STATS_VERSION: u32 = 2;
+
+fn parse_field<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("字段 {key} 的值无效: {value}"))
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+/// 计费方式: 服务商按哪部分流量计费
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BillingMode {
+    /// 上行 + 下行
+    #[default]
+    Sum,
+    /// max(上行, 下行)
+    Max,
+    /// 仅上行
+    Upload,
+    /// 仅下行
+    Download,
+}
+
+impl BillingMode {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "sum" => Ok(Self::Sum),
+            "max" => Ok(Self::Max),
+            "up" | "upload" => Ok(Self::Upload),
+            "down" | "download" => Ok(Self::Download),
+            _ => Err(format!(
+                "未知的计费方式: {value} (可选 sum / max / upload / download)"
+            )),
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Sum => "sum",
+            Self::Max => "max",
+            Self::Upload => "upload",
+            Self::Download => "download",
+        }
+    }
+
+    /// 按计费方式折算的用量
+    pub fn billed(self, up: u64, down: u64) -> u64 {
+        match self {
+            Self::Sum => up.saturating_add(down),
+            Self::Max => up.max(down),
+            Self::Upload => up,
+            Self::Download => down,
+        }
+    }
+}
+
+/// 本周期的计费用量与配额
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficUsage {
+    /// 按计费方式折算后的已用流量 (bytes)
+    pub billed: u64,
+    /// 每周期流量配额 (bytes)，0 表示不限
+    pub quota: u64,
+}
+
+impl TrafficUsage {
+    /// 剩余配额，未设置配额时返回 None
+    pub fn remaining(&self) -> Option<u64> {
+        (self.quota > 0).then(|| self.quota.saturating_sub(self.billed))
+    }
+
+    /// 配额使用百分比 (可能超过 100)，未设置配额时返回 None
+    #[allow(clippy::cast_precision_loss)]
+    pub fn percent_used(&self) -> Option<f64> {
+        (self.quota > 0).then(|| self.billed as f64 / self.quota as f64 * 100.0)
+    }
+}
+
+impl Display for TrafficUsage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.remaining(), self.percent_used()) {
+            (Some(remaining), Some(percent)) => write!(
+                f,
+                "已用 {} / {} ({percent:.2}%)，剩余 {}",
+                format_bytes(self.billed),
+                format_bytes(self.quota),
+                format_bytes(remaining)
+            ),
+            _ => write!(f, "已用 {} (未设置配额)", format_bytes(self.billed)),
+        }
+    }
+}
+
+/// 单个网卡的系统累计流量计数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceCounter {
+    pub name: String,
+    pub total_up: u64,
+    pub total_down: u64,
+}
+
+/// 单个网卡在本周期的流量统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceTraffic {
+    /// 周期内累计上行流量 (bytes)
+    pub cycle_up: u64,
+    /// 周期内累计下行流量 (bytes)
+    pub cycle_down: u64,
+    /// 上次记录时该网卡的系统总上行流量
+    pub last_total_up: u64,
+    /// 上次记录时该网卡的系统总下行流量
+    pub last_total_down: u64,
+    /// 本进程上次采样时该网卡是否存在 (不持久化)
+    pub active: bool,
+}
 
 /// 流量统计数据
 #[derive(Debug, Clone, Default)]
@@ -16,38 +146,44 @@ pub struct TrafficStats {
     pub cycle_up: u64,
     /// 周期内累计下行流量 (bytes)
     pub cycle_down: u64,
-    /// 上次记录时的系统总上行流量 (用于计算增量)
+    /// 上次记录时各网卡总上行流量之和 (增量按网卡计算，此值用于兼容旧版统计文件)
     pub last_total_up: u64,
-    /// 上次记录时的系统总下行流量 (用于计算增量)
+    /// 上次记录时各网卡总下行流量之和
     pub last_total_down: u64,
+    /// 各网卡本周期的流量，用于查看配额被哪张网卡消耗
+    pub interfaces: BTreeMap<String, InterfaceTraffic>,
+    /// 当天日期 (计费时区)，尚未开始统计时为 (0, 0, 0)
+    pub day: (i32, u32, u32),
+    /// 当天开始时的周期累计上行流量，用于计算每日流量
+    pub day_start_up: u64,
+    /// 当天开始时的周期累计下行流量
+    pub day_start_down: u64,
+    /// 本周期内已触发的配额阈值 (百分比)
+    pub fired_thresholds: Vec<u32>,
+    /// 本进程内是否已完成首次采样 (不持久化)
+    primed: bool,
+    /// 统计文件路径
+    pub path: PathBuf,
+    /// 计算计费周期边界所用的时区
+    pub timezone: BillingTimezone,
 }
 
 impl TrafficStats {
-    #[cfg(target_os = "windows")]
-    fn get_stats_path() -> String {
-        std::env::var("PROGRAMDATA").map_or_else(
-            |_| "C:\\ProgramData\\komari-monitor\\traffic_stats.dat".to_string(),
-            |p| format!("{p}\\komari-monitor\\traffic_stats.dat"),
-        )
-    }
-
-    #[cfg(not(target_os = "windows"))]
-    fn get_stats_path() -> String {
-        "/var/lib/komari-monitor/traffic_stats.dat".to_string()
-    }
-
     /// 从文件加载统计数据，如果文件不存在则创建新的
-    pub fn load_or_create(billing_day: u32) -> Self {
-        let path = Self::get_stats_path();
+    pub fn load_or_create(data_dir: &Path, billing_day: u32, timezone: BillingTimezone) -> Self {
+        let path = data_dir.join("traffic_stats.dat");
 
-        if let Some(stats) = Self::load_from_file(&path) {
+        if let Some(mut stats) = Self::load_from_file(&path) {
             // 检查 billing_day 是否变化，如果变化则重置
             if stats.billing_day != billing_day {
                 warn!(
                     "计费日已从 {} 变更为 {}，将重置流量统计",
                     stats.billing_day, billing_day
                 );
-                let new_stats = Self::new_cycle(billing_day);
+                stats.path.clone_from(&path);
+                stats.archive_day();
+                stats.archive_cycle();
+                let new_stats = Self::new_cycle(path, billing_day, timezone);
                 new_stats.save();
                 return new_stats;
             }
@@ -59,18 +195,28 @@ impl TrafficStats {
                 format_bytes(stats.cycle_up),
                 format_bytes(stats.cycle_down)
             );
+            stats.path = path;
+            stats.timezone = timezone;
             stats
         } else {
             info!("未找到流量统计文件，创建新的统计周期");
-            let stats = Self::new_cycle(billing_day);
+            let stats = Self::new_cycle(path, billing_day, timezone);
             stats.save();
             stats
         }
     }
 
+    /// 只读加载统计文件，不存在或损坏时返回 None，供命令行查看使用
+    pub fn read(data_dir: &Path) -> Option<Self> {
+        let path = data_dir.join("traffic_stats.dat");
+        let mut stats = Self::load_from_file(&path)?;
+        stats.path = path;
+        Some(stats)
+    }
+
     /// 创建新的计费周期
-    fn new_cycle(billing_day: u32) -> Self {
-        let (year, month, day) = current_date();
+    fn new_cycle(path: PathBuf, billing_day: u32, timezone: BillingTimezone) -> Self {
+        let (year, month, day) = current_date(&timezone);
         let (cycle_year, cycle_month) = calculate_cycle_start(year, month, day, billing_day);
 
         Self {
@@ -81,121 +227,438 @@ impl TrafficStats {
             cycle_down: 0,
             last_total_up: 0,
             last_total_down: 0,
+            interfaces: BTreeMap::new(),
+            day: (year, month, day),
+            day_start_up: 0,
+            day_start_down: 0,
+            fired_thresholds: Vec::new(),
+            primed: false,
+            path,
+            timezone,
+        }
+    }
+
+    /// 从文件加载，主文件损坏时回退到 `.bak` 备份
+    fn load_from_file(primary: &Path) -> Option<Self> {
+        let error = match Self::read_file(primary) {
+            Ok(stats) => {
+                if fs::read_to_string(primary).is_ok_and(|c| !c.starts_with(STATS_MAGIC)) {
+                    info!("检测到旧版流量统计格式，将在下次保存时迁移");
+                }
+                return Some(stats);
+            }
+            Err(e) => e,
+        };
+        if primary.exists() {
+            error!("流量统计文件损坏 ({error})，尝试从备份恢复");
+        }
+
+        let backup = backup_path(primary);
+        match Self::read_file(&backup) {
+            Ok(stats) => {
+                warn!("已从备份文件 {} 恢复流量统计", backup.display());
+                Some(stats)
+            }
+            Err(e) => {
+                if backup.exists() {
+                    error!("流量统计备份文件同样无法使用: {e}");
+                }
+                None
+            }
         }
     }
 
-    /// 从文件加载
-    fn load_from_file(path: &str) -> Option<Self> {
-        let file = fs::File::open(path).ok()?;
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
+    fn read_file(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&content)
+    }
 
-        // 格式: cycle_year,cycle_month,billing_day,cycle_up,cycle_down,last_total_up,last_total_down
-        let line = lines.next()?.ok()?;
-        let parts: Vec<&str> = line.trim().split(',').collect();
+    /// 解析统计文件内容，兼容旧版 7 字段 CSV 格式
+    fn parse(content: &str) -> Result<Self, String> {
+        let mut lines = content.lines();
+        let header = lines.next().ok_or("文件为空")?.trim();
+
+        let Some(rest) = header.strip_prefix(STATS_MAGIC) else {
+            return Self::parse_legacy(header);
+        };
+
+        let (version, checksum) = rest.trim().split_once(" crc32=").ok_or("文件头格式错误")?;
+        let version: u32 = version
+            .trim_start_matches('v')
+            .parse()
+            .map_err(|_| "无法解析文件版本")?;
+        if version > STATS_VERSION {
+            return Err(format!("不支持的文件版本: v{version}"));
+        }
+
+        let body = content.split_once('\n').map_or("", |(_, body)| body);
+        let expected = u32::from_str_radix(checksum.trim(), 16).map_err(|_| "无法解析校验值")?;
+        if crc32(body.as_bytes()) != expected {
+            return Err("校验失败".to_string());
+        }
+
+        let mut stats = Self::default();
+        let mut seen = 0;
+        for line in body.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "cycle_year" => stats.cycle_year = parse_field(key, value)?,
+                "cycle_month" => stats.cycle_month = parse_field(key, value)?,
+                "billing_day" => stats.billing_day = parse_field(key, value)?,
+                "cycle_up" => stats.cycle_up = parse_field(key, value)?,
+                "cycle_down" => stats.cycle_down = parse_field(key, value)?,
+                "last_total_up" => stats.last_total_up = parse_field(key, value)?,
+                "last_total_down" => stats.last_total_down = parse_field(key, value)?,
+                // 可选字段，不计入必需字段数
+                "fired_thresholds" => {
+                    stats.fired_thresholds = value
+                        .split(',')
+                        .filter(|t| !t.trim().is_empty())
+                        .map(|t| parse_field(key, t))
+                        .collect::<Result<_, _>>()?;
+                    continue;
+                }
+                "day" => {
+                    let fields: Vec<&str> = value.split(',').collect();
+                    let [date, start_up, start_down] = fields[..] else {
+                        return Err("字段 day 格式错误".to_string());
+                    };
+                    stats.day = parse_date(date).ok_or("字段 day 的日期无效")?;
+                    stats.day_start_up = parse_field(key, start_up)?;
+                    stats.day_start_down = parse_field(key, start_down)?;
+                    continue;
+                }
+                _ if key.starts_with("iface.") => {
+                    let name = &key["iface.".len()..];
+                    let fields: Vec<&str> = value.split(',').collect();
+                    let [cycle_up, cycle_down, last_total_up, last_total_down] = fields[..] else {
+                        return Err(format!("网卡 {name} 的统计格式错误"));
+                    };
+                    stats.interfaces.insert(
+                        name.to_string(),
+                        InterfaceTraffic {
+                            cycle_up: parse_field(key, cycle_up)?,
+                            cycle_down: parse_field(key, cycle_down)?,
+                            last_total_up: parse_field(key, last_total_up)?,
+                            last_total_down: parse_field(key, last_total_down)?,
+                            active: false,
+                        },
+                    );
+                    continue;
+                }
+                // 忽略未知字段，便于降级运行
+                _ => continue,
+            }
+            seen += 1;
+        }
+        if seen < 7 {
+            return Err("缺少必需字段".to_string());
+        }
+
+        Ok(stats)
+    }
+
+    /// 旧版格式: `cycle_year,cycle_month,billing_day,cycle_up,cycle_down,last_total_up,last_total_down`
+    fn parse_legacy(line: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = line.split(',').collect();
 
         if parts.len() != 7 {
-            error!("流量统计文件格式错误");
-            return None;
+            return Err("流量统计文件格式错误".to_string());
         }
 
-        Some(Self {
-            cycle_year: parts[0].parse().ok()?,
-            cycle_month: parts[1].parse().ok()?,
-            billing_day: parts[2].parse().ok()?,
-            cycle_up: parts[3].parse().ok()?,
-            cycle_down: parts[4].parse().ok()?,
-            last_total_up: parts[5].parse().ok()?,
-            last_total_down: parts[6].parse().ok()?,
+        Ok(Self {
+            cycle_year: parse_field("cycle_year", parts[0])?,
+            cycle_month: parse_field("cycle_month", parts[1])?,
+            billing_day: parse_field("billing_day", parts[2])?,
+            cycle_up: parse_field("cycle_up", parts[3])?,
+            cycle_down: parse_field("cycle_down", parts[4])?,
+            last_total_up: parse_field("last_total_up", parts[5])?,
+            last_total_down: parse_field("last_total_down", parts[6])?,
+            ..Self::default()
         })
     }
 
-    /// 保存到文件
+    fn serialize(&self) -> String {
+        let mut body = String::with_capacity(256);
+        let _ = writeln!(body, "cycle_year={}", self.cycle_year);
+        let _ = writeln!(body, "cycle_month={}", self.cycle_month);
+        let _ = writeln!(body, "billing_day={}", self.billing_day);
+        let _ = writeln!(body, "cycle_up={}", self.cycle_up);
+        let _ = writeln!(body, "cycle_down={}", self.cycle_down);
+        let _ = writeln!(body, "last_total_up={}", self.last_total_up);
+        let _ = writeln!(body, "last_total_down={}", self.last_total_down);
+        let fired: Vec<String> = self
+            .fired_thresholds
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let _ = writeln!(body, "fired_thresholds={}", fired.join(","));
+        if self.day.0 != 0 {
+            let _ = writeln!(
+                body,
+                "day={},{},{}",
+                format_date(self.day),
+                self.day_start_up,
+                self.day_start_down
+            );
+        }
+        for (name, iface) in &self.interfaces {
+            let _ = writeln!(
+                body,
+                "iface.{name}={},{},{},{}",
+                iface.cycle_up, iface.cycle_down, iface.last_total_up, iface.last_total_down
+            );
+        }
+
+        format!(
+            "{STATS_MAGIC} v{STATS_VERSION} crc32={:08x}\n{body}",
+            crc32(body.as_bytes())
+        )
+    }
+
+    /// 原子保存到文件，保留上一份完好的文件作为 `.bak`
     pub fn save(&self) {
-        let path = Self::get_stats_path();
+        let path = self.path.as_path();
 
-        // 确保目录存在
-        if let Some(parent) = Path::new(&path).parent()
-            && let Err(e) = fs::create_dir_all(parent)
+        // 仅在当前文件完好时轮换备份，避免损坏的文件覆盖可用的备份；
+        // 备份为复制而非改名，写入新文件期间主文件始终存在
+        if let Ok(content) = fs::read_to_string(path)
+            && Self::parse(&content).is_ok()
+            && let Err(e) = write_atomic(&backup_path(path), content.as_bytes())
         {
-            error!("无法创建统计目录: {e}");
+            warn!("备份流量统计文件失败: {e}");
+        }
+
+        match write_atomic(path, self.serialize().as_bytes()) {
+            Ok(()) => trace!("流量统计已保存"),
+            Err(e) => error!("写入流量统计文件失败: {e}"),
+        }
+    }
+
+    /// 按计费方式计算本周期的用量与剩余配额
+    pub fn usage(&self, mode: BillingMode, quota: u64) -> TrafficUsage {
+        TrafficUsage {
+            billed: mode.billed(self.cycle_up, self.cycle_down),
+            quota,
+        }
+    }
+
+    /// 本周期开始日期 (计费日超过当月天数时为月末)
+    fn cycle_start_date(&self) -> (i32, u32, u32) {
+        let day = self
+            .billing_day
+            .clamp(1, days_in_month(self.cycle_year, self.cycle_month));
+        (self.cycle_year, self.cycle_month, day)
+    }
+
+    /// 进行中的计费周期
+    pub fn current_cycle_record(&self) -> HistoryRecord {
+        HistoryRecord {
+            kind: HistoryKind::Cycle,
+            date: format_date(self.cycle_start_date()),
+            up: self.cycle_up,
+            down: self.cycle_down,
+        }
+    }
+
+    /// 当天的流量，尚未开始统计时返回 None
+    pub fn current_day_record(&self) -> Option<HistoryRecord> {
+        (self.day.0 != 0).then(|| HistoryRecord {
+            kind: HistoryKind::Day,
+            date: format_date(self.day),
+            up: self.cycle_up.saturating_sub(self.day_start_up),
+            down: self.cycle_down.saturating_sub(self.day_start_down),
+        })
+    }
+
+    fn append_history(&self, record: &HistoryRecord) {
+        if self.path.as_os_str().is_empty() {
             return;
         }
+        let path = self.path.with_file_name(HISTORY_FILE);
+        if let Err(e) = traffic_history::append(&path, record) {
+            error!("写入流量历史失败: {e}");
+        }
+    }
 
-        let content = format!(
-            "{},{},{},{},{},{},{}",
-            self.cycle_year,
-            self.cycle_month,
-            self.billing_day,
-            self.cycle_up,
-            self.cycle_down,
-            self.last_total_up,
-            self.last_total_down
-        );
+    /// 将当天的流量写入历史
+    fn archive_day(&self) {
+        if let Some(record) = self.current_day_record() {
+            self.append_history(&record);
+        }
+    }
+
+    /// 将本周期的流量写入历史
+    fn archive_cycle(&self) {
+        self.append_history(&self.current_cycle_record());
+    }
+
+    /// 各网卡本周期流量的摘要，按网卡名排序
+    pub fn interface_summary(&self) -> String {
+        let parts: Vec<String> = self
+            .interfaces
+            .iter()
+            .map(|(name, iface)| {
+                format!(
+                    "{name} (上行 {}, 下行 {})",
+                    format_bytes(iface.cycle_up),
+                    format_bytes(iface.cycle_down)
+                )
+            })
+            .collect();
+        parts.join(", ")
+    }
+
+    /// 按网卡计算本次采样的流量增量 (up, down)，并累加到各网卡的周期流量
+    ///
+    /// 启动后首次采样时新出现的网卡从当前计数开始统计；此后新出现的网卡视为刚刚创建，
+    /// 当前计数全部计入。已消失的网卡保留周期流量，不影响总量。
+    fn update_interfaces(&mut self, counters: &[InterfaceCounter]) -> (u64, u64) {
+        for (name, iface) in &mut self.interfaces {
+            if iface.active && !counters.iter().any(|c| &c.name == name) {
+                debug!("网卡 {name} 已消失");
+                iface.active = false;
+            }
+        }
 
-        match fs::File::create(&path) {
-            Ok(mut file) => {
-                if let Err(e) = file.write_all(content.as_bytes()) {
-                    error!("写入流量统计文件失败: {e}");
+        let (mut delta_up, mut delta_down) = (0u64, 0u64);
+        for counter in counters {
+            let Some(iface) = self.interfaces.get_mut(&counter.name) else {
+                debug!("开始统计网卡 {} 的流量", counter.name);
+                let (up, down) = if self.primed {
+                    (counter.total_up, counter.total_down)
                 } else {
-                    trace!("流量统计已保存");
-                }
+                    (0, 0)
+                };
+                self.interfaces.insert(
+                    counter.name.clone(),
+                    InterfaceTraffic {
+                        cycle_up: up,
+                        cycle_down: down,
+                        last_total_up: counter.total_up,
+                        last_total_down: counter.total_down,
+                        active: true,
+                    },
+                );
+                delta_up = delta_up.saturating_add(up);
+                delta_down = delta_down.saturating_add(down);
+                continue;
+            };
+
+            if counter.total_up < iface.last_total_up || counter.total_down < iface.last_total_down
+            {
+                debug!(
+                    "网卡 {} 的流量计数器变小，上行: {} -> {}, 下行: {} -> {}",
+                    counter.name,
+                    iface.last_total_up,
+                    counter.total_up,
+                    iface.last_total_down,
+                    counter.total_down
+                );
+            }
+            let up = counter_delta(iface.last_total_up, counter.total_up, iface.active);
+            let down = counter_delta(iface.last_total_down, counter.total_down, iface.active);
+            iface.cycle_up = iface.cycle_up.saturating_add(up);
+            iface.cycle_down = iface.cycle_down.saturating_add(down);
+            iface.last_total_up = counter.total_up;
+            iface.last_total_down = counter.total_down;
+            iface.active = true;
+            delta_up = delta_up.saturating_add(up);
+            delta_down = delta_down.saturating_add(down);
+        }
+
+        (delta_up, delta_down)
+    }
+
+    /// 网卡筛选变更后，以新纳入统计的网卡的当前计数为起点，不计入此前的流量
+    ///
+    /// 否则首次出现的网卡会被视为刚刚创建而计入全部计数，重新纳入的网卡会计入被排除期间的流量。
+    pub fn rebaseline(&mut self, counters: &[InterfaceCounter]) {
+        for counter in counters {
+            let iface = self.interfaces.entry(counter.name.clone()).or_default();
+            if iface.active {
+                continue;
             }
-            Err(e) => error!("创建流量统计文件失败: {e}"),
+            debug!("网卡筛选变更，从当前计数开始统计网卡 {}", counter.name);
+            iface.last_total_up = counter.total_up;
+            iface.last_total_down = counter.total_down;
+            iface.active = true;
         }
     }
 
     /// 更新流量统计，返回当前周期的累计流量 (up, down)
-    pub fn update(&mut self, current_total_up: u64, current_total_down: u64) -> (u64, u64) {
+    pub fn update(&mut self, counters: &[InterfaceCounter]) -> (u64, u64) {
+        let today = current_date(&self.timezone);
+        self.update_at(today, counters)
+    }
+
+    fn update_at(&mut self, today: (i32, u32, u32), counters: &[InterfaceCounter]) -> (u64, u64) {
+        // 跨天时归档前一天的流量
+        if self.day != today {
+            self.archive_day();
+            self.day = today;
+            self.day_start_up = self.cycle_up;
+            self.day_start_down = self.cycle_down;
+        }
+
         // 检查是否需要重置周期
-        let (year, month, day) = current_date();
+        let (year, month, day) = today;
         let (expected_cycle_year, expected_cycle_month) =
             calculate_cycle_start(year, month, day, self.billing_day);
 
-        if expected_cycle_year != self.cycle_year || expected_cycle_month != self.cycle_month {
+        let new_cycle =
+            expected_cycle_year != self.cycle_year || expected_cycle_month != self.cycle_month;
+        if new_cycle {
             info!(
                 "进入新的计费周期: {}-{:02} -> {}-{:02}",
                 self.cycle_year, self.cycle_month, expected_cycle_year, expected_cycle_month
             );
+            self.archive_cycle();
             self.cycle_year = expected_cycle_year;
             self.cycle_month = expected_cycle_month;
             self.cycle_up = 0;
             self.cycle_down = 0;
-            self.last_total_up = current_total_up;
-            self.last_total_down = current_total_down;
-            self.save();
-            return (0, 0);
+            self.day_start_up = 0;
+            self.day_start_down = 0;
+            for iface in self.interfaces.values_mut() {
+                iface.cycle_up = 0;
+                iface.cycle_down = 0;
+            }
+            self.fired_thresholds.clear();
         }
 
-        // 计算增量
-        // 处理系统重启或计数器溢出的情况
-        let delta_up = if current_total_up >= self.last_total_up {
-            current_total_up - self.last_total_up
-        } else {
-            // 计数器可能溢出或系统重启，使用当前值作为增量
-            debug!(
-                "检测到上行流量计数器重置，当前: {}, 上次: {}",
-                current_total_up, self.last_total_up
-            );
-            current_total_up
-        };
+        let current_total_up: u64 = counters.iter().map(|c| c.total_up).sum();
+        let current_total_down: u64 = counters.iter().map(|c| c.total_down).sum();
 
-        let delta_down = if current_total_down >= self.last_total_down {
-            current_total_down - self.last_total_down
+        // 旧版统计文件只有汇总计数，升级后首次采样仍按汇总计数计算停机期间的增量
+        let legacy = if !self.primed
+            && self.interfaces.is_empty()
+            && (self.last_total_up > 0 || self.last_total_down > 0)
+        {
+            Some((
+                current_total_up.saturating_sub(self.last_total_up),
+                current_total_down.saturating_sub(self.last_total_down),
+            ))
         } else {
-            debug!(
-                "检测到下行流量计数器重置，当前: {}, 上次: {}",
-                current_total_down, self.last_total_down
-            );
-            current_total_down
+            None
         };
 
+        let (delta_up, delta_down) = self.update_interfaces(counters);
+        let (delta_up, delta_down) = legacy.unwrap_or((delta_up, delta_down));
+        self.primed = true;
+
         // 更新累计值
         self.cycle_up = self.cycle_up.saturating_add(delta_up);
         self.cycle_down = self.cycle_down.saturating_add(delta_down);
         self.last_total_up = current_total_up;
         self.last_total_down = current_total_down;
 
+        if new_cycle {
+            self.save();
+        }
+
         trace!(
             "流量统计更新: 周期累计上行 {}, 周期累计下行 {}",
             format_bytes(self.cycle_up),
@@ -206,26 +669,45 @@ impl TrafficStats {
     }
 }
 
-/// 获取当前日期 (year, month, day)
-#[allow(clippy::cast_possible_wrap)]
-fn current_date() -> (i32, u32, u32) {
+/// 32 位计数器的量程
+const COUNTER_32_RANGE: u64 = 1 << 32;
+
+/// 计算单个计数器的增量
+///
+/// 计数器变小时，若该网卡在本进程内被连续观测、上次值在 32 位范围内且按回绕计算的增量不足半个量程，
+/// 视为 32 位计数器回绕；否则视为计数器重置 (网卡重建、系统重启等)，当前值即为重置后的流量。
+fn counter_delta(last: u64, current: u64, continuous: bool) -> u64 {
+    if current >= last {
+        return current - last;
+    }
+    if continuous && last < COUNTER_32_RANGE {
+        let wrapped = COUNTER_32_RANGE - last + current;
+        if wrapped < COUNTER_32_RANGE / 2 {
+            return wrapped;
+        }
+    }
+    current
+}
+
+/// 获取计费时区下的当前日期 (year, month, day)
+fn current_date(timezone: &BillingTimezone) -> (i32, u32, u32) {
     use std::time::{SystemTime, UNIX_EPOCH};
 
     let secs = SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+        .map_or(0, |d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX));
 
-    // 简单的日期计算（考虑时区偏移，这里使用 UTC+8）
-    #[cfg(not(test))]
-    let secs = secs + 8 * 3600; // UTC+8
+    local_date(secs, timezone)
+}
 
-    let days = (secs / 86400) as i32;
+/// 将 UTC 时刻 (Unix 秒) 换算为指定时区的日期 (year, month, day)
+#[allow(clippy::cast_possible_truncation)]
+fn local_date(unix_secs: i64, timezone: &BillingTimezone) -> (i32, u32, u32) {
+    let local_secs = unix_secs + i64::from(timezone.offset_at(unix_secs));
+    let days = local_secs.div_euclid(86400) as i32;
 
     // 从 1970-01-01 计算日期
-    let (year, month, day) = days_to_ymd(days + 719_468); // 719468 是从公元0年到1970年的天数
-
-    (year, month, day)
+    days_to_ymd(days + 719_468) // 719468 是从公元0年到1970年的天数
 }
 
 /// 将天数转换为年月日 (基于 Rata Die 算法)
@@ -244,9 +726,31 @@ fn days_to_ymd(days: i32) -> (i32, u32, u32) {
     (y, m, d)
 }
 
+fn format_date((year, month, day): (i32, u32, u32)) -> String {
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn parse_date(value: &str) -> Option<(i32, u32, u32)> {
+    let mut parts = value.trim().splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    Some((year, month, day))
+}
+
+/// 指定月份的天数
+fn days_in_month(year: i32, month: u32) -> u32 {
+    u8::try_from(month)
+        .ok()
+        .and_then(|m| time::Month::try_from(m).ok())
+        .map_or(31, |m| u32::from(m.length(year)))
+}
+
 /// 计算当前属于哪个计费周期的开始年月
+///
+/// 计费日超过当月天数时按月末计算，如 `billing_day = 31` 时 2 月的周期从 28/29 日开始。
 fn calculate_cycle_start(year: i32, month: u32, day: u32, billing_day: u32) -> (i32, u32) {
-    if day >= billing_day {
+    if day >= billing_day.min(days_in_month(year, month)) {
         // 当前月的计费周期
         (year, month)
     } else {
@@ -260,7 +764,7 @@ fn calculate_cycle_start(year: i32, month: u32, day: u32, billing_day: u32) -> (
 }
 
 /// 格式化字节数为人类可读格式
-fn format_bytes(bytes: u64) -> String {
+pub fn format_bytes(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
     const GB: u64 = MB * 1024;
@@ -298,10 +802,334 @@ mod tests {
         assert_eq!(calculate_cycle_start(2025, 1, 3, 15), (2024, 12));
     }
 
+    #[test]
+    fn test_calculate_cycle_start_month_end() {
+        // 计费日为31号: 小月与2月按月末开始新周期
+        assert_eq!(calculate_cycle_start(2025, 4, 29, 31), (2025, 3));
+        assert_eq!(calculate_cycle_start(2025, 4, 30, 31), (2025, 4));
+        assert_eq!(calculate_cycle_start(2025, 2, 27, 31), (2025, 1));
+        assert_eq!(calculate_cycle_start(2025, 2, 28, 31), (2025, 2));
+        assert_eq!(calculate_cycle_start(2025, 3, 1, 31), (2025, 2));
+        assert_eq!(calculate_cycle_start(2025, 3, 30, 31), (2025, 2));
+        assert_eq!(calculate_cycle_start(2025, 3, 31, 31), (2025, 3));
+
+        // 闰年2月
+        assert_eq!(calculate_cycle_start(2024, 2, 28, 30), (2024, 1));
+        assert_eq!(calculate_cycle_start(2024, 2, 29, 30), (2024, 2));
+        assert_eq!(calculate_cycle_start(2024, 2, 29, 29), (2024, 2));
+        assert_eq!(calculate_cycle_start(2025, 2, 28, 29), (2025, 2));
+
+        // 跨年
+        assert_eq!(calculate_cycle_start(2026, 1, 30, 31), (2025, 12));
+        assert_eq!(calculate_cycle_start(2025, 12, 31, 31), (2025, 12));
+    }
+
+    fn counter(name: &str, total_up: u64, total_down: u64) -> InterfaceCounter {
+        InterfaceCounter {
+            name: name.to_string(),
+            total_up,
+            total_down,
+        }
+    }
+
+    #[test]
+    fn test_per_interface_accounting() {
+        let mut stats = TrafficStats::new_cycle(PathBuf::new(), 1, BillingTimezone::default());
+
+        // 启动时已存在的网卡不计入历史流量
+        stats.update(&[counter("eth0", 100, 200)]);
+        // 运行中新出现的网卡是刚创建的，当前计数全部计入
+        stats.update(&[counter("eth0", 150, 260), counter("wlan0", 10, 10)]);
+        stats.update(&[counter("eth0", 170, 300), counter("wlan0", 30, 40)]);
+
+        let eth0 = &stats.interfaces["eth0"];
+        assert_eq!((eth0.cycle_up, eth0.cycle_down), (70, 100));
+        let wlan0 = &stats.interfaces["wlan0"];
+        assert_eq!((wlan0.cycle_up, wlan0.cycle_down), (30, 40));
+        assert_eq!((stats.cycle_up, stats.cycle_down), (100, 140));
+        assert_eq!(
+            stats.interface_summary(),
+            "eth0 (上行 70 B, 下行 100 B), wlan0 (上行 30 B, 下行 40 B)"
+        );
+    }
+
+    #[test]
+    fn test_interface_disappears_and_reappears() {
+        let mut stats = TrafficStats::new_cycle(PathBuf::new(), 1, BillingTimezone::default());
+
+        stats.update(&[counter("eth0", 1000, 1000), counter("ppp0", 5000, 8000)]);
+        stats.update(&[counter("eth0", 1100, 1200), counter("ppp0", 5500, 8800)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (600, 1000));
+
+        // PPP 重拨期间网卡消失，总量不应因计数之和变小而重复计入
+        stats.update(&[counter("eth0", 1150, 1300)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (650, 1100));
+        stats.update(&[counter("eth0", 1200, 1400)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (700, 1200));
+
+        // 重拨后计数器从 0 开始
+        stats.update(&[counter("eth0", 1200, 1400), counter("ppp0", 30, 70)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (730, 1270));
+        let ppp0 = &stats.interfaces["ppp0"];
+        assert_eq!((ppp0.cycle_up, ppp0.cycle_down), (530, 870));
+
+        // 重新出现但计数器未重置时只计入差值
+        stats.update(&[counter("eth0", 1200, 1400)]);
+        stats.update(&[counter("eth0", 1200, 1400), counter("ppp0", 40, 90)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (740, 1290));
+    }
+
+    #[test]
+    fn test_rebaseline_on_filter_change() {
+        let mut stats = TrafficStats::new_cycle(PathBuf::new(), 1, BillingTimezone::default());
+        stats.update(&[counter("eth0", 1000, 1000), counter("eth1", 50, 50)]);
+        stats.update(&[counter("eth0", 1100, 1200), counter("eth1", 60, 70)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (110, 220));
+
+        // eth1 被排除期间的流量不计入
+        stats.update(&[counter("eth0", 1100, 1200)]);
+        // 新纳入的 wg0 不计入其历史计数
+        let counters = [
+            counter("eth0", 1100, 1200),
+            counter("eth1", 5000, 5000),
+            counter("wg0", 9000, 9000),
+        ];
+        stats.rebaseline(&counters);
+        stats.update(&counters);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (110, 220));
+
+        stats.update(&[
+            counter("eth0", 1110, 1210),
+            counter("eth1", 5001, 5002),
+            counter("wg0", 9003, 9004),
+        ]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (124, 236));
+        assert_eq!(stats.interfaces["wg0"].cycle_up, 3);
+    }
+
+    #[test]
+    fn test_counter_wrap_and_reset() {
+        let mut stats = TrafficStats::new_cycle(PathBuf::new(), 1, BillingTimezone::default());
+        let near_max = u64::from(u32::MAX) - 99;
+
+        stats.update(&[counter("eth0", near_max, 1000)]);
+        // 32 位计数器回绕: 上行增量为 100 + 50
+        stats.update(&[counter("eth0", 50, 1500)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (150, 500));
+
+        // 计数器重置 (回绕增量超过半个量程)，当前值即为增量
+        stats.update(&[counter("eth0", 10, 20)]);
+        assert_eq!((stats.cycle_up, stats.cycle_down), (160, 520));
+
+        // 64 位计数器变小只可能是重置
+        stats.update(&[counter("eth0", 1 << 40, 1 << 40)]);
+        stats.update(&[counter("eth0", 10, 10)]);
+        let expected_up = 160 + (1 << 40);
+        assert_eq!(stats.cycle_up, expected_up);
+
+        // 重启后首次采样无法确认连续性，计数器变小按重置处理
+        let mut restarted = TrafficStats::parse(&stats.serialize()).unwrap();
+        restarted.interfaces.get_mut("eth0").unwrap().last_total_up = near_max;
+        restarted.update(&[counter("eth0", 50, 10)]);
+        assert_eq!(restarted.cycle_up, expected_up + 50);
+    }
+
+    #[test]
+    fn test_legacy_stats_first_update() {
+        // 旧版统计文件没有网卡明细，首次采样按汇总计数计入停机期间的流量
+        let mut stats = TrafficStats::parse("2025,11,1,100,200,1000,2000\n").unwrap();
+        stats.update_at(
+            (2025, 11, 15),
+            &[counter("eth0", 800, 1500), counter("wlan0", 300, 600)],
+        );
+        assert_eq!((stats.cycle_up, stats.cycle_down), (200, 300));
+
+        stats.update_at(
+            (2025, 11, 15),
+            &[counter("eth0", 900, 1500), counter("wlan0", 300, 650)],
+        );
+        assert_eq!((stats.cycle_up, stats.cycle_down), (300, 350));
+    }
+
+    #[test]
+    fn test_history_archive() {
+        let dir = std::env::temp_dir().join(format!("komari-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut stats = TrafficStats {
+            cycle_year: 2025,
+            cycle_month: 11,
+            billing_day: 1,
+            day: (2025, 11, 30),
+            path: dir.join("traffic_stats.dat"),
+            ..Default::default()
+        };
+
+        stats.update_at((2025, 11, 30), &[counter("eth0", 0, 0)]);
+        stats.update_at((2025, 11, 30), &[counter("eth0", 100, 200)]);
+        // 跨天且进入新周期: 归档 11-30 当天与 11 月周期
+        stats.update_at((2025, 12, 1), &[counter("eth0", 150, 260)]);
+        stats.update_at((2025, 12, 1), &[counter("eth0", 160, 280)]);
+        stats.update_at((2025, 12, 2), &[counter("eth0", 165, 290)]);
+
+        let records = traffic_history::load(&dir.join(HISTORY_FILE));
+        let summary: Vec<(HistoryKind, &str, u64, u64)> = records
+            .iter()
+            .map(|r| (r.kind, r.date.as_str(), r.up, r.down))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (HistoryKind::Day, "2025-11-30", 100, 200),
+                (HistoryKind::Cycle, "2025-11-01", 100, 200),
+                // 跨越周期边界那次采样的流量计入新周期
+                (HistoryKind::Day, "2025-12-01", 60, 80),
+            ]
+        );
+
+        let today = stats.current_day_record().unwrap();
+        assert_eq!(
+            (today.date.as_str(), today.up, today.down),
+            ("2025-12-02", 5, 10)
+        );
+        let cycle = stats.current_cycle_record();
+        assert_eq!(
+            (cycle.date.as_str(), cycle.up, cycle.down),
+            ("2025-12-01", 65, 90)
+        );
+
+        // 当天信息随统计文件持久化
+        let parsed = TrafficStats::parse(&stats.serialize()).unwrap();
+        assert_eq!(parsed.day, (2025, 12, 2));
+        assert_eq!((parsed.day_start_up, parsed.day_start_down), (60, 80));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_billing_mode_usage() {
+        let stats = TrafficStats {
+            cycle_up: 300,
+            cycle_down: 700,
+            ..Default::default()
+        };
+
+        assert_eq!(stats.usage(BillingMode::Sum, 0).billed, 1000);
+        assert_eq!(stats.usage(BillingMode::Max, 0).billed, 700);
+        assert_eq!(stats.usage(BillingMode::Upload, 0).billed, 300);
+        assert_eq!(stats.usage(BillingMode::Download, 0).billed, 700);
+
+        // 未设置配额
+        let usage = stats.usage(BillingMode::Sum, 0);
+        assert_eq!(usage.remaining(), None);
+        assert_eq!(usage.percent_used(), None);
+
+        let usage = stats.usage(BillingMode::Max, 2000);
+        assert_eq!(usage.remaining(), Some(1300));
+        assert_eq!(usage.percent_used(), Some(35.0));
+
+        // 超出配额
+        let usage = stats.usage(BillingMode::Sum, 800);
+        assert_eq!(usage.remaining(), Some(0));
+        assert_eq!(usage.percent_used(), Some(125.0));
+
+        assert_eq!(BillingMode::parse("Upload"), Ok(BillingMode::Upload));
+        assert_eq!(BillingMode::parse("down"), Ok(BillingMode::Download));
+        assert!(BillingMode::parse("avg").is_err());
+    }
+
+    #[test]
+    fn test_stats_file_roundtrip() {
+        let stats = TrafficStats {
+            cycle_year: 2025,
+            cycle_month: 12,
+            billing_day: 15,
+            cycle_up: 1024,
+            cycle_down: 4096,
+            last_total_up: 10,
+            last_total_down: 20,
+            fired_thresholds: vec![90, 100],
+            interfaces: BTreeMap::from([(
+                "eth0".to_string(),
+                InterfaceTraffic {
+                    cycle_up: 1000,
+                    cycle_down: 4000,
+                    last_total_up: 5,
+                    last_total_down: 6,
+                    active: false,
+                },
+            )]),
+            ..Default::default()
+        };
+        let content = stats.serialize();
+        assert!(content.starts_with("KOMARI-TRAFFIC-STATS v2 crc32="));
+
+        let parsed = TrafficStats::parse(&content).unwrap();
+        assert_eq!(parsed.serialize(), content);
+
+        // 内容被截断或篡改时校验失败
+        assert!(TrafficStats::parse(&content.replace("4096", "4097")).is_err());
+        assert!(TrafficStats::parse(&content[..content.len() - 5]).is_err());
+        assert!(TrafficStats::parse("").is_err());
+    }
+
+    #[test]
+    fn test_save_keeps_backup() {
+        let dir = std::env::temp_dir().join(format!("komari-stats-{}", std::process::id()));
+        let path = dir.join("traffic_stats.dat");
+        let mut stats = TrafficStats {
+            path: path.clone(),
+            cycle_up: 1,
+            ..Default::default()
+        };
+        stats.save();
+        stats.cycle_up = 2;
+        stats.save();
+
+        assert_eq!(TrafficStats::read_file(&path).unwrap().cycle_up, 2);
+        assert_eq!(
+            TrafficStats::read_file(&backup_path(&path))
+                .unwrap()
+                .cycle_up,
+            1
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_stats_file_legacy_migration() {
+        let stats = TrafficStats::parse("2025,11,1,100,200,300,400\n").unwrap();
+        assert_eq!(
+            (stats.cycle_year, stats.cycle_month, stats.billing_day),
+            (2025, 11, 1)
+        );
+        assert_eq!((stats.cycle_up, stats.cycle_down), (100, 200));
+        assert_eq!((stats.last_total_up, stats.last_total_down), (300, 400));
+
+        assert!(TrafficStats::parse("2025,11,1,100,200").is_err());
+    }
+
     #[test]
     fn test_days_to_ymd() {
         // 2025-12-03 对应的天数
-        let (y, m, d) = days_to_ymd(739215);
+        let (y, m, d) = days_to_ymd(739_893);
         assert_eq!((y, m, d), (2025, 12, 3));
     }
+    #[test]
+    fn test_billing_cycle_in_timezone() {
+        // 2025-12-01T07:30Z 在洛杉矶仍是 11 月 30 日，周期尚未切换
+        let los_angeles = BillingTimezone::parse("PST8PDT,M3.2.0,M11.1.0").unwrap();
+        let (y, m, d) = local_date(1_764_574_200, &los_angeles);
+        assert_eq!((y, m, d), (2025, 11, 30));
+        assert_eq!(calculate_cycle_start(y, m, d, 1), (2025, 11));
+
+        // 2025-03-31T22:30Z 在柏林 (夏令时 UTC+2) 已是 4 月 1 日
+        let berlin = BillingTimezone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        let (y, m, d) = local_date(1_743_460_200, &berlin);
+        assert_eq!((y, m, d), (2025, 4, 1));
+        assert_eq!(calculate_cycle_start(y, m, d, 1), (2025, 4));
+
+        // 同一时刻按固定 UTC+1 计算仍在 3 月
+        let fixed = BillingTimezone::parse("+01:00").unwrap();
+        assert_eq!(local_date(1_743_460_200, &fixed), (2025, 3, 31));
+    }
 }
@@ -7,37 +7,69 @@
     clippy::too_many_lines
 )]
 
-use crate::callbacks::handle_callbacks;
-use crate::command_parser::parse_args;
-use crate::data_struct::{BasicInfo, RealTimeInfo};
-use crate::get_info::network::traffic_stats::TrafficStats;
-use crate::utils::{build_urls, connect_ws, init_logger};
-use futures::stream::SplitSink;
-use futures::{SinkExt, StreamExt};
-use log::{error, info};
-use miniserde::json;
-use std::sync::Arc;
+use crate::command_parser::{Command, EXIT_CONFIG, EXIT_FAILURE, parse_args};
+use crate::config::{Config, LoadOptions};
+use crate::get_info::network::traffic_control;
+use crate::get_info::network::traffic_history::print_history;
+use crate::reporter::{run_reporter, server_config};
+use crate::sampler::SampleSink;
+use crate::shutdown::{SHUTDOWN_TIMEOUT, ShutdownController, wait_for_signal};
+use crate::storage::instance_data_dir;
+use crate::utils::{build_urls, init_logger};
+use log::{error, info, warn};
+use std::path::PathBuf;
 use std::time::Duration;
-use sysinfo::{CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, Networks, RefreshKind};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
-use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time::timeout;
 
 mod callbacks;
 mod command_parser;
+mod commands;
 mod config;
+mod config_parser;
 mod data_struct;
 mod get_info;
+mod proxy;
+mod quota;
+mod reload;
+mod reporter;
 mod rustls_config;
+mod sampler;
+mod shutdown;
+mod storage;
+mod timezone;
 mod utils;
 mod auto_update;
 
 #[tokio::main]
 async fn main() {
-    let config = parse_args();
+    match parse_args() {
+        Command::Run(config, path, options) => run_agent(config, path, options).await,
+        Command::Check(config, path) => std::process::exit(commands::check(&config, &path).await),
+        Command::Info(config) => std::process::exit(commands::info(&config).await),
+        Command::PrintConfig(config, origin) => {
+            std::process::exit(commands::print_config(&config, origin));
+        }
+        Command::TrafficHistory(config, options) => print_history(&config, &options),
+        Command::TrafficShow(config, json) => exit_on_error(traffic_control::show(&config, json)),
+        Command::TrafficAdjust(config, adjustment) => {
+            exit_on_error(traffic_control::adjust(&config, &adjustment));
+        }
+        Command::ConfigSet(path, settings) => {
+            std::process::exit(commands::config_set(&path, &settings));
+        }
+        Command::ConfigGet(config, key) => std::process::exit(commands::config_get(&config, &key)),
+    }
+}
 
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("错误: {e}");
+        std::process::exit(EXIT_FAILURE);
+    }
+}
+
+async fn run_agent(config: Config, path: PathBuf, options: LoadOptions) {
     init_logger(config.log_level);
 
     #[cfg(all(feature = "nyquest-support", not(target_os = "linux")))]
@@ -52,129 +84,105 @@ async fn main() {
         info!("komari-monitor-rs 正在使用 Nyquest 作为 Http Client，该功能暂未稳定，请谨慎使用");
     }
 
-    let connection_urls =
-        build_urls(&config.http_server, config.ws_server.as_ref(), &config.token).unwrap();
+    // 顶层主端与各 [server] 配置块，每个主端一个上报连接
+    let mut servers = Vec::new();
+    for server in config.servers() {
+        // 证书或私钥文件无效时直接退出，避免每次连接都失败
+        let urls = build_urls(&server)
+            .and_then(|urls| rustls_config::panel_tls_config(&server).map(|_| urls));
+        match urls {
+            Ok(urls) => servers.push((server.server_name, urls)),
+            Err(e) => {
+                match &server.server_name {
+                    Some(name) => error!("[server] {name}: {e}"),
+                    None => error!("{e}"),
+                }
+                std::process::exit(EXIT_CONFIG);
+            }
+        }
+    }
 
     info!("成功读取配置: {config:?}");
 
     // 启动自动升级检查任务
     if config.auto_update > 0 {
-        let update_repo = config.update_repo.clone();
-        let ignore_cert = config.ignore_unsafe_cert;
+        let update_config = config.clone();
         let interval_hours = config.auto_update;
         std::thread::spawn(move || {
             loop {
-                auto_update::check_and_upgrade(&update_repo, ignore_cert);
+                auto_update::check_and_upgrade(&update_config);
                 std::thread::sleep(Duration::from_secs(interval_hours * 3600));
             }
         });
         info!("自动升级已启用，检查间隔: {interval_hours} 小时");
     }
 
-    loop {
-        let Ok(ws_stream) = connect_ws(
-            &connection_urls.ws_real_time,
-            config.tls,
-            config.ignore_unsafe_cert,
-        )
-        .await
-        else {
-            error!("无法连接到 Websocket 服务器，5 秒后重新尝试");
-            sleep(Duration::from_secs(5)).await;
-            continue;
-        };
-
-        let (write, mut read) = ws_stream.split();
-
-        let locked_write: Arc<
-            Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>,
-        > = Arc::new(Mutex::new(write));
-
-        // Handle callbacks
-        {
-            let config_cloned = config.clone();
-            let connection_urls_cloned = connection_urls.clone();
-            let locked_write_cloned = locked_write.clone();
-            let _listener = tokio::spawn(async move {
-                handle_callbacks(
-                    &config_cloned,
-                    &connection_urls_cloned,
-                    &mut read,
-                    &locked_write_cloned,
-                )
-                .await;
-            });
-        }
+    let data_dir = instance_data_dir(&config);
+    info!("数据目录: {}", data_dir.display());
 
-        let mut sysinfo_sys = sysinfo::System::new();
-        let mut networks = Networks::new_with_refreshed_list();
-        let mut disks = Disks::new();
-        sysinfo_sys.refresh_cpu_list(
-            CpuRefreshKind::nothing()
-                .without_cpu_usage()
-                .without_frequency(),
-        );
-        sysinfo_sys.refresh_memory_specifics(MemoryRefreshKind::everything());
-
-        let basic_info = BasicInfo::build(&sysinfo_sys, config.fake, &config.ip_provider).await;
-
-        basic_info.push(connection_urls.basic_info.clone(), config.ignore_unsafe_cert);
-
-        // 初始化流量统计
-        let mut traffic_stats = TrafficStats::load_or_create(config.billing_day);
-
-        // 设置初始的系统总流量（用于计算增量）
-        if traffic_stats.last_total_up == 0 && traffic_stats.last_total_down == 0 {
-            let (total_up, total_down) =
-                crate::get_info::network::get_system_total_traffic(&networks);
-            traffic_stats.last_total_up = total_up;
-            traffic_stats.last_total_down = total_down;
-            traffic_stats.save();
+    // 持有数据目录锁直到退出，命令行据此判断 Agent 是否在运行
+    let _lock = match traffic_control::lock_data_dir(&data_dir) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(EXIT_FAILURE);
         }
-
-        // 保存计数器，用于定期持久化
-        let mut save_counter: u32 = 0;
-
-        loop {
-            let start_time = tokio::time::Instant::now();
-            sysinfo_sys.refresh_specifics(
-                RefreshKind::nothing()
-                    .with_cpu(CpuRefreshKind::everything().without_frequency())
-                    .with_memory(MemoryRefreshKind::everything()),
-            );
-            networks.refresh(true);
-            disks.refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
-            let real_time = RealTimeInfo::build(
-                &sysinfo_sys,
-                &networks,
-                &disks,
-                &mut traffic_stats,
-                config.realtime_info_interval,
-                config.fake,
-            );
-
-            // 每 60 次上报保存一次流量统计（默认间隔下约 1 分钟）
-            save_counter += 1;
-            if save_counter >= 60 {
-                traffic_stats.save();
-                save_counter = 0;
-            }
-
-            let json = json::to_string(&real_time);
-            {
-                let mut write = locked_write.lock().await;
-                if let Err(e) = write.send(Message::Text(Utf8Bytes::from(json))).await {
-                    error!("推送 RealTime 时发生错误，尝试重新连接: {e}");
-                    break;
-                }
-            }
-            let end_time = start_time.elapsed();
-
-            sleep(Duration::from_millis({
-                let end = u64::try_from(end_time.as_millis()).unwrap_or(0);
-                config.realtime_info_interval.saturating_sub(end)
-            }))
+    };
+
+    let shutdown = ShutdownController::new();
+    let (config_tx, config_rx) = watch::channel(config);
+
+    // 共用一个采样循环，每个样本发送给全部上报端
+    let mut sample_txs = Vec::new();
+    let mut reporters = Vec::new();
+    for (name, endpoints) in servers {
+        let (sample_tx, sample_rx) = mpsc::channel(64);
+        sample_txs.push(SampleSink::new(name.as_deref(), sample_tx));
+        let config_rx = server_config(config_rx.clone(), name);
+        let data_dir = data_dir.clone();
+        let reporter_handle = shutdown.handle();
+        reporters.push(tokio::spawn(async move {
+            run_reporter(
+                config_rx,
+                endpoints,
+                &data_dir,
+                sample_rx,
+                reporter_handle,
+            )
             .await;
+        }));
+    }
+
+    let sampler = tokio::spawn(sampler::run_sampler(
+        config_tx.subscribe(),
+        data_dir.clone(),
+        sample_txs,
+        shutdown.handle(),
+    ));
+
+    // SIGHUP 或配置文件修改时热重载
+    tokio::spawn(reload::watch_config(
+        path,
+        options,
+        config_tx,
+        shutdown.handle(),
+    ));
+
+    wait_for_signal().await;
+    shutdown.trigger();
+
+    // 采样端保存流量统计后退出，上报端随之发送 Close 帧
+    let stopped = timeout(SHUTDOWN_TIMEOUT, async {
+        let _ = sampler.await;
+        for reporter in reporters {
+            let _ = reporter.await;
         }
+    })
+    .await;
+    if stopped.is_err() {
+        warn!("等待上报任务退出超时");
     }
+
+    shutdown.wait_tasks(SHUTDOWN_TIMEOUT).await;
+    info!("komari-monitor-rs 已退出");
 }
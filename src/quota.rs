@@ -0,0 +1,248 @@
+use crate::get_info::network::traffic_stats::{TrafficStats, TrafficUsage};
+use log::{error, info, warn};
+use std::fmt::{Display, Formatter};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// 流量达到阈值后执行的本地动作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaActionKind {
+    /// 仅记录日志
+    Log,
+    /// 执行 Hook 命令 (通过 shell 执行)
+    Exec(String),
+    /// 关闭指定网卡
+    InterfaceDown(String),
+}
+
+/// 配额阈值动作，对应配置中的一行 `quota_action = "<百分比> <动作> [参数]"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaAction {
+    /// 触发阈值 (配额百分比，可超过 100)
+    pub threshold: u32,
+    pub kind: QuotaActionKind,
+}
+
+impl QuotaAction {
+    /// 解析 `90 log`、`100 exec /path/to/hook.sh`、`100 ifdown eth0` 形式的配置
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (threshold, rest) = value
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("quota_action 格式错误: {value}"))?;
+        let threshold: u32 = threshold
+            .trim_end_matches('%')
+            .parse()
+            .ok()
+            .filter(|t| *t > 0)
+            .ok_or_else(|| format!("quota_action 阈值必须为正整数百分比: {threshold}"))?;
+
+        let rest = rest.trim_start();
+        let (action, arg) = rest
+            .split_once(char::is_whitespace)
+            .map_or((rest, ""), |(action, arg)| (action, arg.trim()));
+
+        let kind = match action {
+            "log" if arg.is_empty() => QuotaActionKind::Log,
+            "exec" if !arg.is_empty() => QuotaActionKind::Exec(arg.to_string()),
+            "ifdown" if !arg.is_empty() && !arg.contains(char::is_whitespace) => {
+                QuotaActionKind::InterfaceDown(arg.to_string())
+            }
+            _ => {
+                return Err(format!(
+                    "quota_action 动作无效: {rest} (可选 log / exec <命令> / ifdown <网卡>)"
+                ));
+            }
+        };
+
+        Ok(Self { threshold, kind })
+    }
+}
+
+impl Display for QuotaAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            QuotaActionKind::Log => write!(f, "{} log", self.threshold),
+            QuotaActionKind::Exec(command) => write!(f, "{} exec {command}", self.threshold),
+            QuotaActionKind::InterfaceDown(name) => write!(f, "{} ifdown {name}", self.threshold),
+        }
+    }
+}
+
+/// 检查配额阈值，触发本周期内尚未触发过的动作
+///
+/// 已触发的阈值记录在 `TrafficStats` 中并随统计文件持久化，重启后不会重复触发。
+/// 有新阈值触发时返回 true，调用方应立即保存统计文件。
+pub fn check_quota(stats: &mut TrafficStats, usage: TrafficUsage, actions: &[QuotaAction]) -> bool {
+    let Some(percent) = usage.percent_used() else {
+        return false;
+    };
+
+    let mut crossed: Vec<u32> = actions
+        .iter()
+        .map(|action| action.threshold)
+        .filter(|threshold| threshold_reached(usage, *threshold))
+        .filter(|threshold| !stats.fired_thresholds.contains(threshold))
+        .collect();
+    if crossed.is_empty() {
+        return false;
+    }
+    crossed.sort_unstable();
+    crossed.dedup();
+
+    for threshold in crossed {
+        warn!(
+            "本周期流量已达到配额的 {threshold}%: {usage}，各网卡: {}",
+            stats.interface_summary()
+        );
+        for action in actions.iter().filter(|a| a.threshold == threshold) {
+            run_action(action, usage, percent);
+        }
+        stats.fired_thresholds.push(threshold);
+    }
+    true
+}
+
+/// 流量被手动下调后，移除不再达到的已触发阈值，使其在本周期内可以再次触发
+pub fn prune_fired_thresholds(stats: &mut TrafficStats, usage: TrafficUsage) {
+    stats
+        .fired_thresholds
+        .retain(|threshold| usage.quota > 0 && threshold_reached(usage, *threshold));
+}
+
+/// 使用整数比较，避免浮点误差导致恰好达到阈值时不触发
+fn threshold_reached(usage: TrafficUsage, threshold: u32) -> bool {
+    u128::from(usage.billed) * 100 >= u128::from(threshold) * u128::from(usage.quota)
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn run_action(action: &QuotaAction, usage: TrafficUsage, percent: f64) {
+    let command = match &action.kind {
+        QuotaActionKind::Log => return,
+        QuotaActionKind::Exec(command) => {
+            let mut cmd = shell_command(command);
+            cmd.env("KOMARI_QUOTA_THRESHOLD", action.threshold.to_string())
+                .env("KOMARI_QUOTA_PERCENT", (percent as u64).to_string())
+                .env("KOMARI_TRAFFIC_BILLED", usage.billed.to_string())
+                .env("KOMARI_TRAFFIC_QUOTA", usage.quota.to_string());
+            cmd
+        }
+        QuotaActionKind::InterfaceDown(name) => interface_down_command(name),
+    };
+    info!("触发配额动作: {action}");
+    spawn_action(action.to_string(), command);
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd.exe");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(windows)]
+fn interface_down_command(name: &str) -> Command {
+    let mut cmd = Command::new("netsh");
+    cmd.args(["interface", "set", "interface"])
+        .arg(format!("name={name}"))
+        .arg("admin=disabled");
+    cmd
+}
+
+#[cfg(not(windows))]
+fn interface_down_command(name: &str) -> Command {
+    let mut cmd = Command::new("ip");
+    cmd.args(["link", "set", "dev", name, "down"]);
+    cmd
+}
+
+/// 后台执行动作命令，不阻塞采样循环
+fn spawn_action(label: String, mut command: Command) {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    tokio::spawn(async move {
+        match command.output().await {
+            Ok(output) if output.status.success() => info!("配额动作 {label} 执行成功"),
+            Ok(output) => warn!(
+                "配额动作 {label} 执行失败 ({}): {}{}",
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => error!("无法执行配额动作 {label}: {e}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quota_action() {
+        assert_eq!(
+            QuotaAction::parse("90 log"),
+            Ok(QuotaAction {
+                threshold: 90,
+                kind: QuotaActionKind::Log
+            })
+        );
+        let exec = QuotaAction::parse("100% exec /usr/local/bin/notify.sh --over").unwrap();
+        assert_eq!(
+            exec.kind,
+            QuotaActionKind::Exec("/usr/local/bin/notify.sh --over".to_string())
+        );
+        assert_eq!(exec.to_string(), "100 exec /usr/local/bin/notify.sh --over");
+        assert_eq!(
+            QuotaAction::parse("110 ifdown eth0").unwrap().kind,
+            QuotaActionKind::InterfaceDown("eth0".to_string())
+        );
+
+        assert!(QuotaAction::parse("log").is_err());
+        assert!(QuotaAction::parse("0 log").is_err());
+        assert!(QuotaAction::parse("90 exec").is_err());
+        assert!(QuotaAction::parse("90 ifdown eth0 eth1").is_err());
+        assert!(QuotaAction::parse("90 reboot").is_err());
+    }
+
+    #[test]
+    fn test_check_quota_fires_once() {
+        let actions = [
+            QuotaAction::parse("90 log").unwrap(),
+            QuotaAction::parse("100 log").unwrap(),
+        ];
+        let mut stats = TrafficStats::default();
+        let usage = |billed| TrafficUsage {
+            billed,
+            quota: 1000,
+        };
+
+        assert!(!check_quota(&mut stats, usage(899), &actions));
+        assert!(check_quota(&mut stats, usage(900), &actions));
+        assert_eq!(stats.fired_thresholds, vec![90]);
+        assert!(!check_quota(&mut stats, usage(950), &actions));
+
+        // 一次跨过多个阈值
+        let mut stats = TrafficStats::default();
+        assert!(check_quota(&mut stats, usage(1200), &actions));
+        assert_eq!(stats.fired_thresholds, vec![90, 100]);
+        assert!(!check_quota(&mut stats, usage(1300), &actions));
+
+        // 未设置配额时不触发
+        let mut stats = TrafficStats::default();
+        let unlimited = TrafficUsage {
+            billed: 1 << 40,
+            quota: 0,
+        };
+        assert!(!check_quota(&mut stats, unlimited, &actions));
+    }
+}
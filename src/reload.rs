@@ -0,0 +1,224 @@
+use crate::config::{Config, LoadOptions};
+use crate::config_parser;
+use crate::rustls_config::panel_tls_config;
+use crate::shutdown::Shutdown;
+use crate::utils::{build_urls, set_log_level};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::time::{MissedTickBehavior, interval};
+
+/// 检查配置文件修改时间的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 修改后需要重启 Agent 才能生效的配置项
+const RESTART_KEYS: [&str; 10] = [
+    "billing_day",
+    "billing_timezone",
+    "data_dir",
+    "instance",
+    "offline_buffer_size",
+    "offline_buffer_max_age",
+    "reconnect_initial_delay",
+    "reconnect_max_delay",
+    "auto_update",
+    "update_repo",
+];
+
+/// 与主端连接相关的配置项，修改后上报端会重新连接
+pub fn connection_changed(old: &Config, new: &Config) -> bool {
+    old.http_server != new.http_server
+        || old.ws_server != new.ws_server
+        || old.token != new.token
+        || old.tls != new.tls
+        || old.ignore_unsafe_cert != new.ignore_unsafe_cert
+        || old.ca_file != new.ca_file
+        || old.pinned_pubkey != new.pinned_pubkey
+        || old.client_cert != new.client_cert
+        || old.client_key != new.client_key
+        || old.proxy != new.proxy
+        || old.no_proxy != new.no_proxy
+}
+
+/// 两份配置之间发生变化的配置项，`[server]` 配置块中的变化记为 `server`
+fn changed_keys(old: &Config, new: &Config) -> Vec<String> {
+    // 以生成的配置文件内容比较，保证覆盖全部配置项
+    let values = |config: &Config| {
+        let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for entry in config_parser::parse(&config.render()).unwrap_or_default() {
+            let key = match entry.section {
+                Some(_) => "server".to_string(),
+                None => entry.key,
+            };
+            values.entry(key).or_default().push(entry.value);
+        }
+        values
+    };
+    let (old_values, new_values) = (values(old), values(new));
+
+    let mut keys: Vec<String> = old_values
+        .keys()
+        .chain(new_values.keys())
+        .filter(|key| old_values.get(*key) != new_values.get(*key))
+        .cloned()
+        .collect();
+    // token_file 内容变化时文件路径不变
+    if old.token != new.token && !keys.iter().any(|k| k == "token" || k == "token_file") {
+        keys.push("token".to_string());
+    }
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// 配置文件与 `include` 引入的文件、目录的修改时间，任一变化即重新加载
+fn modified_times(path: &Path, config: &Config) -> Vec<Option<SystemTime>> {
+    std::iter::once(path)
+        .chain(config.included_paths.iter().map(PathBuf::as_path))
+        .map(modified_time)
+        .collect()
+}
+
+/// 重新加载配置，新配置无效时保留当前配置
+fn reload(path: &Path, options: &LoadOptions, tx: &watch::Sender<Config>) {
+    let new = match Config::load(path, options) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("新配置无效，继续使用当前配置: {e}");
+            return;
+        }
+    };
+    let servers = new.servers();
+    let valid = servers.iter().try_for_each(|server| {
+        build_urls(server)
+            .and_then(|_| panel_tls_config(server))
+            .map(|_| ())
+    });
+    if let Err(e) = valid {
+        error!("新配置无效，继续使用当前配置: {e}");
+        return;
+    }
+
+    let keys = changed_keys(&tx.borrow(), &new);
+    if keys.is_empty() {
+        info!("配置未变化");
+        return;
+    }
+    info!("配置已重新加载，变更项: {}", keys.join(", "));
+
+    let restart: Vec<&str> = keys
+        .iter()
+        .map(String::as_str)
+        .filter(|key| RESTART_KEYS.contains(key))
+        .collect();
+    if !restart.is_empty() {
+        warn!("以下配置项需要重启 Agent 才能生效: {}", restart.join(", "));
+    }
+    // 已有配置块的修改立即生效，新增或删除配置块需要重启
+    let names = |servers: Vec<Config>| -> Vec<Option<String>> {
+        servers
+            .into_iter()
+            .map(|server| server.server_name)
+            .collect()
+    };
+    if names(tx.borrow().servers()) != names(servers) {
+        warn!("新增或删除 [server] 配置块需要重启 Agent 才能生效");
+    }
+
+    set_log_level(new.log_level);
+    tx.send_replace(new);
+}
+
+/// 监听 SIGHUP 与配置文件 (含 `include` 引入的文件) 变化，重新加载配置并通知各任务
+pub async fn watch_config(
+    path: PathBuf,
+    options: LoadOptions,
+    tx: watch::Sender<Config>,
+    mut shutdown: Shutdown,
+) {
+    #[cfg(unix)]
+    let mut hangup = {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("无法监听 SIGHUP: {e}");
+                None
+            }
+        }
+    };
+
+    let mut poll = interval(POLL_INTERVAL);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_modified = modified_times(&path, &tx.borrow());
+
+    loop {
+        #[cfg(unix)]
+        let hangup_recv = async {
+            match hangup.as_mut() {
+                Some(hangup) => hangup.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hangup_recv = std::future::pending::<Option<()>>();
+
+        tokio::select! {
+            () = shutdown.recv() => return,
+            _ = hangup_recv => {
+                info!("收到 SIGHUP，重新加载配置");
+                reload(&path, &options, &tx);
+                last_modified = modified_times(&path, &tx.borrow());
+            }
+            _ = poll.tick() => {
+                if modified_times(&path, &tx.borrow()) != last_modified {
+                    info!("配置文件已修改，重新加载配置");
+                    reload(&path, &options, &tx);
+                    last_modified = modified_times(&path, &tx.borrow());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_keys() {
+        let old = Config {
+            http_server: vec!["http://a".to_string()],
+            token: "t".to_string(),
+            ..Config::default()
+        };
+        let mut new = old.clone();
+        assert!(changed_keys(&old, &new).is_empty());
+
+        new.realtime_info_interval = 2000;
+        new.terminal = true;
+        new.token = "u".to_string();
+        assert_eq!(
+            changed_keys(&old, &new),
+            [
+                "realtime_info_interval",
+                "terminal",
+                "terminal_entry",
+                "token"
+            ]
+        );
+        assert!(connection_changed(&old, &new));
+
+        new.token_file = Some("/run/secrets/token".to_string());
+        let mut newer = new.clone();
+        newer.token = "v".to_string();
+        assert_eq!(changed_keys(&new, &newer), ["token"]);
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 带 Full Jitter 的指数退避
+///
+/// 第 n 次失败后的等待时间在 `[0, min(max, initial * 2^n)]` 内均匀随机，
+/// 避免大量 Agent 在主端重启后同时重连。
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+    rng: u64,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::from(d.subsec_nanos()) ^ d.as_secs());
+        // 混入 PID，同一时刻启动的多个 Agent 也能得到不同的随机序列
+        let seed = nanos ^ (u64::from(std::process::id()) << 32) ^ 0x9E37_79B9_7F4A_7C15;
+
+        Self {
+            initial,
+            max: max.max(initial),
+            attempts: 0,
+            rng: seed | 1,
+        }
+    }
+
+    /// 连续失败次数
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// 当前退避上限 (未加抖动)
+    pub fn ceiling(&self) -> Duration {
+        let factor = 1u32.checked_shl(self.attempts.min(31)).unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+
+    /// 记录一次失败并返回本次应等待的时间
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.ceiling();
+        self.attempts = self.attempts.saturating_add(1);
+
+        let millis = u64::try_from(ceiling.as_millis()).unwrap_or(u64::MAX);
+        if millis == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(self.next_random() % (millis + 1))
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// xorshift64*，仅用于抖动，不需要密码学强度
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_growth_and_reset() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(30));
+        let mut ceilings = Vec::new();
+        for _ in 0..7 {
+            let ceiling = backoff.ceiling();
+            assert!(backoff.next_delay() <= ceiling);
+            ceilings.push(ceiling.as_secs());
+        }
+        assert_eq!(ceilings, vec![1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(backoff.attempts(), 7);
+
+        backoff.reset();
+        assert_eq!(backoff.attempts(), 0);
+        assert_eq!(backoff.ceiling(), Duration::from_secs(1));
+    }
+}
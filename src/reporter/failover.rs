@@ -0,0 +1,132 @@
+use crate::utils::ConnectionUrls;
+use std::path::{Path, PathBuf};
+
+/// 主端地址的故障转移
+///
+/// 当前地址连续连接失败 `failover_after` 次后切换到下一个地址，最后一个地址之后回到主地址；
+/// 使用备用地址期间由上报端定期检查主地址，主地址恢复后切回。
+pub struct Failover {
+    /// 按顺序排列的地址，第一个为主地址，至少有一个
+    endpoints: Vec<ConnectionUrls>,
+    active: usize,
+    /// 当前地址的连续失败次数
+    failures: u32,
+}
+
+impl Failover {
+    pub fn new(endpoints: Vec<ConnectionUrls>) -> Self {
+        Self {
+            endpoints,
+            active: 0,
+            failures: 0,
+        }
+    }
+
+    /// 当前使用的地址
+    pub fn active(&self) -> &ConnectionUrls {
+        &self.endpoints[self.active]
+    }
+
+    pub fn primary(&self) -> &ConnectionUrls {
+        &self.endpoints[0]
+    }
+
+    /// 当前是否在使用备用地址
+    pub fn on_backup(&self) -> bool {
+        self.active > 0
+    }
+
+    /// 记录一次连接失败，达到 `failover_after` 次时切换到下一个地址并返回 true
+    pub fn record_failure(&mut self, failover_after: u32) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        if failover_after == 0 || self.endpoints.len() < 2 || self.failures < failover_after {
+            return false;
+        }
+        self.active = (self.active + 1) % self.endpoints.len();
+        self.failures = 0;
+        true
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// 切回主地址
+    pub fn fall_back(&mut self) {
+        self.active = 0;
+        self.failures = 0;
+    }
+}
+
+/// 记录当前地址的状态文件，`info` 据此显示运行中的 Agent 正在使用的地址
+pub fn active_endpoint_file(data_dir: &Path, server_name: Option<&str>) -> PathBuf {
+    match server_name {
+        Some(name) => data_dir.join(format!("active_endpoint-{name}")),
+        None => data_dir.join("active_endpoint"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(endpoint: &str) -> ConnectionUrls {
+        ConnectionUrls {
+            endpoint: endpoint.to_string(),
+            basic_info: String::new(),
+            exec_callback: String::new(),
+            ws_terminal: String::new(),
+            ws_real_time: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_failover_order() {
+        let mut failover = Failover::new(vec![urls("a"), urls("b"), urls("c")]);
+        assert!(!failover.record_failure(2));
+        failover.record_success();
+        assert!(!failover.record_failure(2));
+        assert!(failover.record_failure(2));
+        assert_eq!(failover.active().endpoint, "b");
+        assert!(failover.on_backup());
+
+        // 最后一个地址之后回到主地址
+        failover.record_failure(1);
+        failover.record_failure(1);
+        assert_eq!(failover.active().endpoint, "a");
+
+        failover.record_failure(1);
+        failover.fall_back();
+        assert_eq!(failover.active().endpoint, "a");
+        assert!(!failover.record_failure(0));
+
+        let mut single = Failover::new(vec![urls("a")]);
+        assert!(!single.record_failure(1));
+    }
+
+    #[test]
+    fn test_failover_then_fall_back() {
+        let mut failover = Failover::new(vec![urls("primary"), urls("backup")]);
+        assert!(!failover.on_backup());
+
+        // 主地址连续失败后切换到备用地址，期间成功连接备用地址
+        for _ in 0..2 {
+            assert!(!failover.record_failure(3));
+        }
+        assert!(failover.record_failure(3));
+        assert_eq!(failover.active().endpoint, "backup");
+        assert!(failover.on_backup());
+        failover.record_success();
+        assert_eq!(failover.primary().endpoint, "primary");
+
+        // 主地址恢复后切回，失败计数重新开始
+        failover.record_failure(3);
+        failover.fall_back();
+        assert!(!failover.on_backup());
+        assert_eq!(failover.active().endpoint, "primary");
+        assert!(!failover.record_failure(3));
+        assert!(!failover.record_failure(3));
+        assert!(failover.record_failure(3));
+        assert_eq!(failover.active().endpoint, "backup");
+    }
+}
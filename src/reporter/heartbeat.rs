@@ -0,0 +1,109 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// 记录上报连接最后一次收到数据 (含 Pong) 的时间，用于识别半开连接
+///
+/// 使用单调时钟，系统时间被 NTP 等调整时不会误判。
+#[derive(Debug, Clone)]
+pub struct Liveness(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    start: Instant,
+    /// 最后一次收到数据的时间 (自 `start` 起的微秒数)
+    last_seen: AtomicU64,
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self(Arc::new(Inner {
+            start: Instant::now(),
+            last_seen: AtomicU64::new(0),
+        }))
+    }
+
+    /// 当前时间 (自创建起的微秒数)，与 `seen_since` 配合使用
+    pub fn now(&self) -> u64 {
+        u64::try_from(self.0.start.elapsed().as_micros()).unwrap_or(u64::MAX)
+    }
+
+    /// 收到任意帧时调用
+    pub fn touch(&self) {
+        self.0.last_seen.store(self.now(), Ordering::Relaxed);
+    }
+
+    /// 自 `since` (`now` 的返回值) 之后是否收到过数据
+    pub fn seen_since(&self, since: u64) -> bool {
+        self.0.last_seen.load(Ordering::Relaxed) > since
+    }
+}
+
+/// 心跳状态: 记录已发送、尚未确认的 Ping
+#[derive(Debug, Default)]
+pub struct Heartbeat {
+    /// 未确认的 Ping 的发送时间 (`Liveness::now`)
+    pending: Option<u64>,
+}
+
+impl Heartbeat {
+    /// 是否可以发送下一个 Ping: 没有未确认的 Ping，或上一个 Ping 之后已收到数据
+    pub fn ready(&self, liveness: &Liveness) -> bool {
+        self.pending
+            .is_none_or(|sent_at| liveness.seen_since(sent_at))
+    }
+
+    /// 即将发送 Ping 时调用
+    pub fn sending(&mut self, liveness: &Liveness) {
+        self.pending = Some(liveness.now());
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// 等待超时后调用，返回连接是否已失效 (Ping 之后未收到任何数据)
+    pub fn expired(&mut self, liveness: &Liveness) -> bool {
+        self.pending
+            .take()
+            .is_some_and(|sent_at| !liveness.seen_since(sent_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen_since() {
+        let liveness = Liveness::new();
+        let before = liveness.now();
+        assert!(!liveness.seen_since(before));
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        liveness.touch();
+        assert!(liveness.seen_since(before));
+        assert!(!liveness.seen_since(liveness.now()));
+    }
+
+    #[test]
+    fn test_heartbeat_timeout() {
+        let liveness = Liveness::new();
+        let mut heartbeat = Heartbeat::default();
+        assert!(heartbeat.ready(&liveness));
+
+        // 收到 Pong 后无需等到超时即可发送下一个 Ping
+        heartbeat.sending(&liveness);
+        assert!(!heartbeat.ready(&liveness));
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        liveness.touch();
+        assert!(heartbeat.ready(&liveness));
+        assert!(!heartbeat.expired(&liveness));
+        assert!(!heartbeat.is_pending());
+
+        // 超时前未收到任何数据，判定连接失效
+        heartbeat.sending(&liveness);
+        assert!(!heartbeat.ready(&liveness));
+        assert!(heartbeat.expired(&liveness));
+        assert!(!heartbeat.is_pending());
+    }
+}
@@ -0,0 +1,555 @@
+use crate::callbacks::{LockedWriter, handle_callbacks};
+use crate::config::Config;
+use crate::data_struct::BasicInfo;
+use crate::reporter::backoff::Backoff;
+use crate::reporter::failover::{Failover, active_endpoint_file};
+use crate::reporter::heartbeat::{Heartbeat, Liveness};
+use crate::reporter::offline_buffer::OfflineBuffer;
+use crate::sampler::{Sample, unix_millis};
+use crate::shutdown::Shutdown;
+use crate::storage::write_atomic;
+use crate::reload::connection_changed;
+use crate::utils::{ConnectionUrls, build_urls, connect_ws, probe_endpoint};
+use futures::{SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use sysinfo::{CpuRefreshKind, MemoryRefreshKind};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, watch};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior, interval, sleep, sleep_until};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::{Bytes, Message, Utf8Bytes};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+pub mod backoff;
+pub mod failover;
+pub mod heartbeat;
+pub mod offline_buffer;
+
+/// 上报 WebSocket 的连接状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// 正在建立连接
+    Connecting,
+    /// 已连接，正常上报
+    Connected,
+    /// 连接失败或断开，等待退避时间后重试
+    Backoff,
+    /// 连续失败次数过多，仍在按退避间隔重试
+    Degraded,
+}
+
+/// 在等待 `fut` 完成的同时将新样本写入离线缓冲区，采样端关闭时返回 None
+async fn buffering<F: Future>(
+    fut: F,
+    rx: &mut Receiver<Sample>,
+    buffer: &mut OfflineBuffer,
+) -> Option<F::Output> {
+    tokio::pin!(fut);
+    loop {
+        tokio::select! {
+            output = &mut fut => return Some(output),
+            sample = rx.recv() => buffer.push(sample?),
+        }
+    }
+}
+
+/// 会话结束时终止回调监听与主地址检查任务，避免重连后旧的任务堆积
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn send_sample(locked_write: &LockedWriter, sample: &Sample) -> Result<(), String> {
+    let mut write = locked_write.lock().await;
+    write
+        .send(Message::Text(Utf8Bytes::from(sample.payload())))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+struct Reporter {
+    /// 日志前缀，`[server]` 配置块为 `[名称] `，顶层主端为空
+    label: String,
+    config: Config,
+    config_rx: watch::Receiver<Config>,
+    failover: Failover,
+    /// 记录当前地址的状态文件
+    active_file: PathBuf,
+    rx: Receiver<Sample>,
+    buffer: OfflineBuffer,
+    shutdown: Shutdown,
+    state: ConnectionState,
+    backoff: Backoff,
+    /// 上次成功推送的 Basic Info，内容未变化时重连不再重复推送
+    last_basic_info: Option<String>,
+    /// 主端连接配置已变更，跳过退避立即重连
+    reconnect_now: bool,
+}
+
+impl Reporter {
+    /// 应用热重载的配置，主端连接配置变更时返回 true
+    fn apply_config(&mut self, new: Config) -> bool {
+        let reconnect = connection_changed(&self.config, &new);
+        if reconnect {
+            match build_urls(&new) {
+                Ok(endpoints) => {
+                    self.failover = Failover::new(endpoints);
+                    self.save_active();
+                }
+                Err(e) => error!("{}无法解析新的主端地址，继续使用当前地址: {e}", self.label),
+            }
+            self.last_basic_info = None;
+        }
+        self.config = new;
+        reconnect
+    }
+
+    /// 记录当前地址，供 `info` 读取
+    fn save_active(&self) {
+        let endpoint = &self.failover.active().endpoint;
+        if let Err(e) = write_atomic(&self.active_file, endpoint.as_bytes()) {
+            warn!("{}无法记录当前主端地址: {e}", self.label);
+        }
+    }
+
+    /// 切换地址后重新推送 Basic Info
+    ///
+    /// 退避不因切换而重置，全部地址均不可用时仍按退避间隔重试。
+    fn switched(&mut self) {
+        self.save_active();
+        self.last_basic_info = None;
+    }
+
+    fn set_state(&mut self, next: ConnectionState) {
+        if self.state == next {
+            return;
+        }
+        if next == ConnectionState::Degraded {
+            warn!(
+                "{}连接状态: {:?} -> {next:?} (已连续失败 {} 次)",
+                self.label,
+                self.state,
+                self.backoff.attempts()
+            );
+        } else {
+            info!("{}连接状态: {:?} -> {next:?}", self.label, self.state);
+        }
+        self.state = next;
+    }
+
+    /// 进入退避状态并等待，采样端关闭时返回 None
+    async fn wait_backoff(&mut self) -> Option<()> {
+        let delay = self.backoff.next_delay();
+        if self.backoff.attempts() >= self.config.reconnect_degraded_after {
+            self.set_state(ConnectionState::Degraded);
+        } else {
+            self.set_state(ConnectionState::Backoff);
+        }
+        info!("{}{} 毫秒后重新连接", self.label, delay.as_millis());
+
+        // 主端连接配置变更时提前结束等待；使用独立的接收端，变更仍由 run 统一应用
+        let mut probe = self.config_rx.clone();
+        let current = &self.config;
+        let label = &self.label;
+        let wait = async move {
+            tokio::select! {
+                () = sleep(delay) => {}
+                () = async {
+                    while probe.changed().await.is_ok() {
+                        if connection_changed(current, &probe.borrow_and_update()) {
+                            info!("{label}主端连接配置已变更，立即重新连接");
+                            return;
+                        }
+                    }
+                    std::future::pending::<()>().await;
+                } => {}
+            }
+        };
+        buffering(wait, &mut self.rx, &mut self.buffer).await
+    }
+
+    async fn run(&mut self) {
+        loop {
+            // 断线期间修改的配置在下一次连接前生效
+            if self.config_rx.has_changed().unwrap_or(false) {
+                let new = self.config_rx.borrow_and_update().clone();
+                if self.apply_config(new) {
+                    self.backoff.reset();
+                }
+            }
+            self.set_state(ConnectionState::Connecting);
+
+            let url = self.failover.active().ws_real_time.clone();
+            let connect = Box::pin(connect_ws(&url, &self.config));
+            let Some(connected) = buffering(connect, &mut self.rx, &mut self.buffer).await else {
+                return;
+            };
+            let ws_stream = match connected {
+                Ok(ws_stream) => ws_stream,
+                Err(e) => {
+                    error!("{}无法连接到 Websocket 服务器: {e}", self.label);
+                    if self.failover.record_failure(self.config.failover_after) {
+                        warn!(
+                            "{}连续 {} 次连接失败，切换主端地址: {}",
+                            self.label,
+                            self.config.failover_after,
+                            self.failover.active().endpoint
+                        );
+                        self.switched();
+                    }
+                    if self.wait_backoff().await.is_none() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            self.failover.record_success();
+            self.set_state(ConnectionState::Connected);
+            info!(
+                "{}当前主端地址: {}",
+                self.label,
+                self.failover.active().endpoint
+            );
+            let connected_at = Instant::now();
+
+            if self.run_session(ws_stream).await.is_none() {
+                return;
+            }
+            if self.reconnect_now {
+                self.reconnect_now = false;
+                self.backoff.reset();
+                continue;
+            }
+
+            // 连接保持足够久才视为恢复稳定，否则继续累积退避时间
+            if connected_at.elapsed() >= Duration::from_secs(self.config.reconnect_stable_after) {
+                self.backoff.reset();
+            }
+            if self.wait_backoff().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    /// 在已建立的连接上推送 Basic Info 与实时样本，连接断开时返回，采样端关闭时返回 None
+    async fn run_session(
+        &mut self,
+        ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ) -> Option<()> {
+        let (write, mut read) = ws_stream.split();
+
+        let locked_write: LockedWriter = Arc::new(Mutex::new(write));
+        let liveness = Liveness::new();
+
+        // Handle callbacks
+        let mut listener = {
+            let config_rx = self.config_rx.clone();
+            let connection_urls_cloned = self.failover.active().clone();
+            let locked_write_cloned = locked_write.clone();
+            let liveness_cloned = liveness.clone();
+            let shutdown_cloned = self.shutdown.clone();
+            AbortOnDrop(tokio::spawn(async move {
+                handle_callbacks(
+                    &config_rx,
+                    &connection_urls_cloned,
+                    &mut read,
+                    &locked_write_cloned,
+                    &liveness_cloned,
+                    &shutdown_cloned,
+                )
+                .await;
+            }))
+        };
+
+        self.sync_basic_info().await?;
+
+        if !self.buffer.is_empty() {
+            info!(
+                "{}开始回放 {} 条离线缓存的 RealTime 样本",
+                self.label,
+                self.buffer.len()
+            );
+        }
+        let replay_interval = Duration::from_secs(1) / self.config.offline_replay_rate.max(1);
+        let mut replay_tick = interval(replay_interval);
+
+        let heartbeat_enabled = self.config.heartbeat_interval > 0;
+        let mut heartbeat_tick =
+            interval(Duration::from_secs(self.config.heartbeat_interval.max(1)));
+        heartbeat_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        heartbeat_tick.reset();
+        let heartbeat_timeout = Duration::from_secs(self.config.heartbeat_timeout);
+        let mut heartbeat = Heartbeat::default();
+        let mut ping_deadline = Instant::now();
+
+        // 使用备用地址时定期检查主地址是否恢复
+        let failback_enabled = self.failover.on_backup() && self.config.failback_interval > 0;
+        let mut failback_tick = interval(Duration::from_secs(self.config.failback_interval.max(1)));
+        failback_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        failback_tick.reset();
+        let mut probe: Option<AbortOnDrop<bool>> = None;
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = &mut listener.0 => {
+                    warn!("{}上报 WebSocket 已被关闭，尝试重新连接", self.label);
+                    return Some(());
+                }
+                () = sleep_until(ping_deadline), if heartbeat.is_pending() => {
+                    if heartbeat.expired(&liveness) {
+                        warn!(
+                            "{}{} 秒内未收到 Pong，判定连接已失效，尝试重新连接",
+                            self.label,
+                            heartbeat_timeout.as_secs()
+                        );
+                        return Some(());
+                    }
+                }
+                // 上一个 Ping 已收到回应即按间隔发送，否则等待超时判定
+                _ = heartbeat_tick.tick(), if heartbeat_enabled && heartbeat.ready(&liveness) => {
+                    heartbeat.sending(&liveness);
+                    let ping = Message::Ping(Bytes::from(unix_millis().to_string()));
+                    if let Err(e) = locked_write.lock().await.send(ping).await {
+                        warn!("{}发送 Ping 时发生错误，尝试重新连接: {e}", self.label);
+                        return Some(());
+                    }
+                    debug!("{}已发送心跳 Ping", self.label);
+                    ping_deadline = Instant::now() + heartbeat_timeout;
+                }
+                sample = self.rx.recv() => {
+                    // 采样端已退出，说明正在关闭 Agent
+                    let Some(sample) = sample else {
+                        let close = Message::Close(Some(CloseFrame {
+                            code: CloseCode::Away,
+                            reason: Utf8Bytes::from_static("agent shutting down"),
+                        }));
+                        if let Err(e) = locked_write.lock().await.send(close).await {
+                            warn!("{}发送 Close 帧失败: {e}", self.label);
+                        } else {
+                            info!("{}已关闭上报 WebSocket 连接", self.label);
+                        }
+                        return None;
+                    };
+                    // 回放未完成时新样本排在缓冲区末尾，保证时间顺序
+                    if !self.buffer.is_empty() {
+                        self.buffer.push(sample);
+                        continue;
+                    }
+                    if let Err(e) = send_sample(&locked_write, &sample).await {
+                        error!("{}推送 RealTime 时发生错误，尝试重新连接: {e}", self.label);
+                        self.buffer.push(sample);
+                        return Some(());
+                    }
+                }
+                Ok(()) = self.config_rx.changed() => {
+                    let new = self.config_rx.borrow_and_update().clone();
+                    if self.apply_config(new) {
+                        info!("{}主端连接配置已变更，重新连接", self.label);
+                        self.reconnect_now = true;
+                        return Some(());
+                    }
+                }
+                _ = failback_tick.tick(), if failback_enabled && probe.is_none() => {
+                    let url = self.failover.primary().ws_real_time.clone();
+                    let config = self.config.clone();
+                    let label = self.label.clone();
+                    // 只检查连接与 TLS 握手，不以 Agent 身份登录主地址
+                    probe = Some(AbortOnDrop(tokio::spawn(async move {
+                        match probe_endpoint(&url, &config).await {
+                            Ok(()) => true,
+                            Err(e) => {
+                                debug!("{label}主地址仍不可用: {e}");
+                                false
+                            }
+                        }
+                    })));
+                }
+                healthy = async {
+                    match probe.as_mut() {
+                        Some(probe) => (&mut probe.0).await.unwrap_or(false),
+                        None => std::future::pending().await,
+                    }
+                }, if probe.is_some() => {
+                    probe = None;
+                    if healthy {
+                        info!(
+                            "{}主地址已恢复，切回主地址: {}",
+                            self.label,
+                            self.failover.primary().endpoint
+                        );
+                        self.failover.fall_back();
+                        self.switched();
+                        self.reconnect_now = true;
+                        return Some(());
+                    }
+                }
+                _ = replay_tick.tick(), if !self.buffer.is_empty() => {
+                    let Some(sample) = self.buffer.front() else {
+                        continue;
+                    };
+                    if let Err(e) = send_sample(&locked_write, sample).await {
+                        warn!("{}回放离线样本时发生错误，尝试重新连接: {e}", self.label);
+                        return Some(());
+                    }
+                    self.buffer.pop_front();
+                    if self.buffer.is_empty() {
+                        info!("{}离线样本回放完成", self.label);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 重新采集 Basic Info，仅在首次连接、内容变化或上次推送失败时推送
+    async fn sync_basic_info(&mut self) -> Option<()> {
+        let mut sysinfo_sys = sysinfo::System::new();
+        sysinfo_sys.refresh_cpu_list(
+            CpuRefreshKind::nothing()
+                .without_cpu_usage()
+                .without_frequency(),
+        );
+        sysinfo_sys.refresh_memory_specifics(MemoryRefreshKind::everything());
+
+        let build = BasicInfo::build(&sysinfo_sys, &self.config);
+        let basic_info = buffering(build, &mut self.rx, &mut self.buffer).await?;
+
+        let json = miniserde::json::to_string(&basic_info);
+        if self.last_basic_info.as_ref() == Some(&json) {
+            debug!("{}Basic Info 未变化，跳过推送", self.label);
+            return Some(());
+        }
+
+        // 推送为阻塞请求，放到阻塞线程池中执行，期间照常缓冲样本
+        let url = self.failover.active().basic_info.clone();
+        let config = self.config.clone();
+        let push = tokio::task::spawn_blocking(move || basic_info.push(url, &config));
+        if buffering(push, &mut self.rx, &mut self.buffer)
+            .await?
+            .unwrap_or(false)
+        {
+            self.last_basic_info = Some(json);
+        }
+        Some(())
+    }
+}
+
+/// 上报循环: 维护 WebSocket 连接，推送实时样本，断线期间缓冲样本并在重连后回放
+pub async fn run_reporter(
+    mut config_rx: watch::Receiver<Config>,
+    endpoints: Vec<ConnectionUrls>,
+    data_dir: &Path,
+    rx: Receiver<Sample>,
+    shutdown: Shutdown,
+) {
+    let config = config_rx.borrow_and_update().clone();
+    // 每个主端使用独立的离线缓冲文件
+    let (label, buffer_file) = match &config.server_name {
+        Some(name) => (format!("[{name}] "), format!("offline_buffer-{name}.dat")),
+        None => (String::new(), "offline_buffer.dat".to_string()),
+    };
+    let buffer = OfflineBuffer::open(
+        data_dir.join(buffer_file),
+        config.offline_buffer_size,
+        config.offline_buffer_max_age,
+    );
+    let backoff = Backoff::new(
+        Duration::from_secs(config.reconnect_initial_delay),
+        Duration::from_secs(config.reconnect_max_delay),
+    );
+
+    let active_file = active_endpoint_file(data_dir, config.server_name.as_deref());
+    let mut reporter = Reporter {
+        label,
+        config,
+        config_rx,
+        failover: Failover::new(endpoints),
+        active_file,
+        rx,
+        buffer,
+        shutdown,
+        state: ConnectionState::Connecting,
+        backoff,
+        last_basic_info: None,
+        reconnect_now: false,
+    };
+    info!("{}连接状态: {:?}", reporter.label, reporter.state);
+    reporter.save_active();
+    reporter.run().await;
+}
+
+/// 单个主端的配置: 跟随热重载的全局配置，按 `server_name` 取出该主端的生效配置
+///
+/// 热重载后配置块被删除时保留原有配置，新增或删除配置块需要重启 Agent。
+pub fn server_config(
+    mut config_rx: watch::Receiver<Config>,
+    name: Option<String>,
+) -> watch::Receiver<Config> {
+    let find = move |config: &Config| {
+        config
+            .servers()
+            .into_iter()
+            .find(|server| server.server_name == name)
+    };
+    let initial = config_rx.borrow_and_update().clone();
+    let (tx, rx) = watch::channel(find(&initial).unwrap_or(initial));
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                () = tx.closed() => return,
+                changed = config_rx.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    let config = config_rx.borrow_and_update().clone();
+                    if let Some(server) = find(&config) {
+                        tx.send_replace(server);
+                    }
+                }
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_server_config() {
+        let load = |server_b: &str| {
+            let mut config = Config::default();
+            let content = format!(
+                "http_server = http://a\ntoken = t\nfake = 2\n[server]\nname = b\n{server_b}"
+            );
+            config.apply_file(&content, false).unwrap();
+            config
+        };
+        let (tx, rx) = watch::channel(load("http_server = http://b"));
+        let primary = server_config(rx.clone(), None);
+        let mut server_b = server_config(rx.clone(), Some("b".to_string()));
+        assert_eq!(primary.borrow().http_server, ["http://a"]);
+        assert_eq!(server_b.borrow().http_server, ["http://b"]);
+        // 配置块之外的配置项与顶层相同
+        assert_eq!(server_b.borrow().server_name.as_deref(), Some("b"));
+        assert!((server_b.borrow().fake - 2.0).abs() < f64::EPSILON);
+
+        // 热重载后各主端取出各自的新配置
+        tx.send_replace(load("http_server = http://b2"));
+        server_b.changed().await.unwrap();
+        assert_eq!(server_b.borrow_and_update().http_server, ["http://b2"]);
+        assert_eq!(primary.borrow().http_server, ["http://a"]);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::sampler::{Sample, unix_millis};
+use crate::storage::write_atomic;
+use log::{debug, error, info, warn};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// 每弹出多少条样本重写一次磁盘文件，避免每次回放都整体重写
+const REWRITE_EVERY: usize = 64;
+
+/// 断线期间的 `RealTimeInfo` 离线缓冲区
+///
+/// 样本同时保存在内存与磁盘文件中 (每行 `<毫秒时间戳>\t<json>`)，
+/// 按字节数与存活时间两个维度限制大小，Agent 重启后仍可继续回放。
+pub struct OfflineBuffer {
+    path: PathBuf,
+    max_bytes: usize,
+    max_age_ms: u64,
+    entries: VecDeque<Sample>,
+    /// 内存中样本的总字节数 (按落盘行长度计算)
+    bytes: usize,
+    /// 磁盘文件当前大小
+    file_bytes: usize,
+    /// 已弹出但尚未从磁盘文件中移除的样本数
+    popped: usize,
+}
+
+fn line_len(sample: &Sample) -> usize {
+    // 时间戳最多 20 位 + 制表符 + 换行
+    sample.json.len() + 22
+}
+
+impl OfflineBuffer {
+    /// 打开 (或创建) 离线缓冲区，`max_bytes` 为 0 时禁用缓冲
+    pub fn open(path: PathBuf, max_bytes: usize, max_age_secs: u64) -> Self {
+        let mut buffer = Self {
+            path,
+            max_bytes,
+            max_age_ms: max_age_secs.saturating_mul(1000),
+            entries: VecDeque::new(),
+            bytes: 0,
+            file_bytes: 0,
+            popped: 0,
+        };
+
+        if !buffer.enabled() {
+            let _ = fs::remove_file(&buffer.path);
+            return buffer;
+        }
+
+        let Ok(content) = fs::read_to_string(&buffer.path) else {
+            return buffer;
+        };
+
+        let mut samples: Vec<Sample> = content
+            .lines()
+            .filter_map(|line| {
+                let (timestamp, json) = line.split_once('\t')?;
+                Some(Sample {
+                    timestamp: timestamp.parse().ok()?,
+                    json: json.to_string(),
+                })
+            })
+            .collect();
+        // 按时间戳排序，保证回放顺序
+        samples.sort_by_key(|s| s.timestamp);
+
+        for sample in samples {
+            buffer.bytes += line_len(&sample);
+            buffer.entries.push_back(sample);
+        }
+        buffer.file_bytes = content.len();
+        buffer.enforce_limits();
+        buffer.rewrite();
+
+        if !buffer.is_empty() {
+            info!("已加载 {} 条离线缓存的 RealTime 样本", buffer.len());
+        }
+
+        buffer
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn front(&self) -> Option<&Sample> {
+        self.entries.front()
+    }
+
+    /// 追加一条样本，超出限制时丢弃最旧的样本
+    pub fn push(&mut self, sample: Sample) {
+        if !self.enabled() {
+            return;
+        }
+
+        let line = format!("{}\t{}\n", sample.timestamp, sample.json);
+        self.bytes += line_len(&sample);
+        self.entries.push_back(sample);
+
+        let dropped = self.enforce_limits();
+
+        // 丢弃过多数据后整体重写文件，否则直接追加
+        if self.popped + dropped > 0 && self.file_bytes > self.max_bytes + self.max_bytes / 4 {
+            self.rewrite();
+        } else {
+            self.append(&line);
+        }
+    }
+
+    /// 移除最旧的一条样本 (已成功回放)
+    pub fn pop_front(&mut self) {
+        if let Some(sample) = self.entries.pop_front() {
+            self.bytes -= line_len(&sample);
+            self.popped += 1;
+        }
+        if self.entries.is_empty() || self.popped >= REWRITE_EVERY {
+            self.rewrite();
+        }
+    }
+
+    /// 按大小与存活时间丢弃最旧的样本，返回丢弃数量
+    fn enforce_limits(&mut self) -> usize {
+        let now = unix_millis();
+        let mut dropped = 0;
+        while let Some(front) = self.entries.front() {
+            let expired = now.saturating_sub(front.timestamp) > self.max_age_ms;
+            if !expired && self.bytes <= self.max_bytes {
+                break;
+            }
+            self.bytes -= line_len(front);
+            self.entries.pop_front();
+            dropped += 1;
+        }
+        if dropped > 0 {
+            debug!("离线缓冲区已满或样本过期，丢弃 {dropped} 条最旧样本");
+        }
+        dropped
+    }
+
+    fn append(&mut self, line: &str) {
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        match result {
+            Ok(()) => self.file_bytes += line.len(),
+            Err(e) => warn!("写入离线缓冲文件失败: {e}"),
+        }
+    }
+
+    /// 用内存中的样本重写磁盘文件
+    fn rewrite(&mut self) {
+        self.popped = 0;
+
+        if self.entries.is_empty() {
+            self.file_bytes = 0;
+            if let Err(e) = fs::remove_file(&self.path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                error!("清理离线缓冲文件失败: {e}");
+            }
+            return;
+        }
+
+        let mut content = String::with_capacity(self.bytes);
+        for sample in &self.entries {
+            content.push_str(&sample.timestamp.to_string());
+            content.push('\t');
+            content.push_str(&sample.json);
+            content.push('\n');
+        }
+
+        match write_atomic(&self.path, content.as_bytes()) {
+            Ok(()) => self.file_bytes = content.len(),
+            Err(e) => error!("重写离线缓冲文件失败: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64) -> Sample {
+        Sample {
+            timestamp,
+            json: format!("{{\"t\":{timestamp}}}"),
+        }
+    }
+
+    #[test]
+    fn test_offline_buffer_persist_and_order() {
+        let path = std::env::temp_dir().join(format!("komari-offline-{}.buf", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let now = unix_millis();
+
+        let mut buffer = OfflineBuffer::open(path.clone(), 1 << 20, 3600);
+        buffer.push(sample(now - 1000));
+        buffer.push(sample(now - 3000));
+        buffer.push(sample(now - 2000));
+        drop(buffer);
+
+        // 重启后按时间戳顺序回放
+        let mut buffer = OfflineBuffer::open(path.clone(), 1 << 20, 3600);
+        let mut replayed = Vec::new();
+        while let Some(front) = buffer.front() {
+            replayed.push(front.timestamp);
+            buffer.pop_front();
+        }
+        assert_eq!(replayed, vec![now - 3000, now - 2000, now - 1000]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_offline_buffer_limits() {
+        let path = std::env::temp_dir().join(format!("komari-limit-{}.buf", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let now = unix_millis();
+
+        // 过期样本被丢弃
+        let mut buffer = OfflineBuffer::open(path.clone(), 1 << 20, 10);
+        buffer.push(sample(now - 60_000));
+        buffer.push(sample(now));
+        assert_eq!(buffer.len(), 1);
+
+        // 超出字节上限时丢弃最旧的样本
+        let limit = line_len(&sample(now)) * 3;
+        let mut buffer = OfflineBuffer::open(path.clone(), limit, 3600);
+        for i in 0..10 {
+            buffer.push(sample(now + i));
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.front().map(|s| s.timestamp), Some(now + 7));
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,9 +1,22 @@
+use crate::config::Config;
+#[cfg(feature = "ureq-support")]
+use crate::proxy::ProxySettings;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rustls::client::WebPkiServerVerifier;
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
-use rustls::{ClientConfig, DigitallySignedStruct, DistinguishedName, Error, SignatureScheme};
-use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::{
+    ClientConfig, DigitallySignedStruct, DistinguishedName, Error, RootCertStore, SignatureScheme,
+};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
 use std::fmt::{Debug, Formatter};
 use std::sync::Arc;
 
+/// 公钥固定的前缀，格式与 curl 的 `--pinnedpubkey` 相同
+const PIN_PREFIX: &str = "sha256//";
+
 struct NoVerification;
 
 impl Debug for NoVerification {
@@ -69,11 +82,384 @@ impl ServerCertVerifier for NoVerification {
     }
 }
 
-pub fn create_dangerous_config() -> ClientConfig {
-    let verifier = Arc::new(NoVerification {});
+/// 在证书验证之外，要求服务端证书的公钥 (SPKI) 的 SHA-256 与其中一个固定值相同
+///
+/// 握手签名始终自行校验，不交给 `inner`：`inner` 为 `NoVerification` 时若同样跳过签名校验，
+/// 中间人只需重放服务端的公开证书即可通过公钥固定。
+struct PinnedVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: Vec<[u8; 32]>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedVerifier {
+    fn new(inner: Arc<dyn ServerCertVerifier>, pins: Vec<[u8; 32]>) -> Self {
+        Self {
+            inner,
+            pins,
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        }
+    }
+}
+
+impl Debug for PinnedVerifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PinnedVerifier")
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let hash = spki_sha256(end_entity)
+            .map_err(|e| Error::General(format!("无法解析服务端证书: {e}")))?;
+        if !self.pins.contains(&hash) {
+            return Err(Error::General(format!(
+                "服务端证书公钥与 pinned_pubkey 不匹配 (实际为 {PIN_PREFIX}{})",
+                BASE64.encode(hash)
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        self.inner.requires_raw_public_keys()
+    }
+
+    fn root_hint_subjects(&self) -> Option<&[DistinguishedName]> {
+        self.inner.root_hint_subjects()
+    }
+}
+
+/// 证书公钥 (DER 编码的 `SubjectPublicKeyInfo`) 的 SHA-256
+fn spki_sha256(cert: &CertificateDer<'_>) -> Result<[u8; 32], String> {
+    let cert = webpki::EndEntityCert::try_from(cert).map_err(|e| e.to_string())?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, &cert.subject_public_key_info());
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(digest.as_ref());
+    Ok(hash)
+}
+
+/// 解析 `sha256//<base64>` 格式的公钥固定值
+pub fn parse_pin(pin: &str) -> Result<[u8; 32], String> {
+    let invalid = || format!("pinned_pubkey 必须为 {PIN_PREFIX}<base64 编码的 SHA-256>: {pin}");
+    let encoded = pin.strip_prefix(PIN_PREFIX).ok_or_else(invalid)?;
+    BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .ok_or_else(invalid)
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| format!("无法读取证书 {path}: {e}"))?;
+    if certs.is_empty() {
+        return Err(format!("{path} 中没有证书"));
+    }
+    Ok(certs)
+}
+
+/// 内置的 Mozilla 根证书，加上 `ca_file` 中的证书
+fn root_store(config: &Config) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    if let Some(ca_file) = &config.ca_file {
+        for cert in load_certs(ca_file)? {
+            roots
+                .add(cert)
+                .map_err(|e| format!("{ca_file} 中的 CA 证书无效: {e}"))?;
+        }
+    }
+    Ok(roots)
+}
+
+/// 连接主端 (WebSocket、Basic Info 上报、Exec 回调) 使用的 TLS 配置
+///
+/// 信任内置根证书与 `ca_file`，校验 `pinned_pubkey` 并发送 `client_cert` 客户端证书。
+/// `ignore_unsafe_cert` 只跳过证书链验证，设置了 `pinned_pubkey` 时仍会校验公钥与握手签名。
+pub fn panel_tls_config(config: &Config) -> Result<Arc<ClientConfig>, String> {
+    let mut verifier: Arc<dyn ServerCertVerifier> = if config.ignore_unsafe_cert {
+        Arc::new(NoVerification)
+    } else {
+        WebPkiServerVerifier::builder(Arc::new(root_store(config)?))
+            .build()
+            .map_err(|e| format!("无法创建证书验证器: {e}"))?
+    };
+    if !config.pinned_pubkey.is_empty() {
+        let pins = config
+            .pinned_pubkey
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<_, _>>()?;
+        verifier = Arc::new(PinnedVerifier::new(verifier, pins));
+    }
 
-    ClientConfig::builder()
+    let builder = ClientConfig::builder()
         .dangerous()
-        .with_custom_certificate_verifier(verifier)
-        .with_no_client_auth()
+        .with_custom_certificate_verifier(verifier);
+    let tls = match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => {
+            let key = PrivateKeyDer::from_pem_file(key)
+                .map_err(|e| format!("无法读取私钥 {key}: {e}"))?;
+            builder
+                .with_client_auth_cert(load_certs(cert)?, key)
+                .map_err(|e| format!("客户端证书无效: {e}"))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err("client_cert 与 client_key 必须同时设置".to_string()),
+    };
+    Ok(Arc::new(tls))
+}
+
+/// 访问其他 HTTPS 服务 (如自动升级) 使用的 TLS 配置，只应用 `ca_file`
+pub fn general_tls_config(config: &Config) -> Result<Arc<ClientConfig>, String> {
+    let tls = ClientConfig::builder()
+        .with_root_certificates(root_store(config)?)
+        .with_no_client_auth();
+    Ok(Arc::new(tls))
+}
+
+/// 使用指定 rustls 配置的 ureq TLS 连接器 (ureq 自带的连接器不支持自定义证书验证)
+#[cfg(feature = "ureq-support")]
+mod ureq_connector {
+    use rustls::{ClientConfig, ClientConnection, StreamOwned};
+    use rustls_pki_types::ServerName;
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+    use ureq::unversioned::transport::{
+        Buffers, ConnectionDetails, Connector, Either, LazyBuffers, NextTimeout, Transport,
+        TransportAdapter,
+    };
+
+    #[derive(Debug)]
+    pub struct RustlsConfigConnector(pub Arc<ClientConfig>);
+
+    impl<In: Transport> Connector<In> for RustlsConfigConnector {
+        type Out = Either<In, RustlsTransport>;
+
+        fn connect(
+            &self,
+            details: &ConnectionDetails,
+            chained: Option<In>,
+        ) -> Result<Option<Self::Out>, ureq::Error> {
+            let Some(transport) = chained else {
+                return Ok(None);
+            };
+            if !details.needs_tls() || transport.is_tls() {
+                return Ok(Some(Either::A(transport)));
+            }
+
+            let host = details
+                .uri
+                .authority()
+                .ok_or(ureq::Error::Tls("URL 缺少主机名"))?
+                .host();
+            let name = ServerName::try_from(host.trim_start_matches('[').trim_end_matches(']'))
+                .map_err(|_| ureq::Error::Tls("无效的主机名"))?
+                .to_owned();
+            let stream = StreamOwned {
+                conn: ClientConnection::new(self.0.clone(), name)?,
+                sock: TransportAdapter::new(transport.boxed()),
+            };
+            let buffers = LazyBuffers::new(
+                details.config.input_buffer_size(),
+                details.config.output_buffer_size(),
+            );
+            Ok(Some(Either::B(RustlsTransport { buffers, stream })))
+        }
+    }
+
+    pub struct RustlsTransport {
+        buffers: LazyBuffers,
+        stream: StreamOwned<ClientConnection, TransportAdapter>,
+    }
+
+    impl std::fmt::Debug for RustlsTransport {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("RustlsTransport")
+        }
+    }
+
+    impl Transport for RustlsTransport {
+        fn buffers(&mut self) -> &mut dyn Buffers {
+            &mut self.buffers
+        }
+
+        fn transmit_output(
+            &mut self,
+            amount: usize,
+            timeout: NextTimeout,
+        ) -> Result<(), ureq::Error> {
+            self.stream.get_mut().set_timeout(timeout);
+            let output = &self.buffers.output()[..amount];
+            self.stream.write_all(output)?;
+            Ok(())
+        }
+
+        fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, ureq::Error> {
+            self.stream.get_mut().set_timeout(timeout);
+            let input = self.buffers.input_append_buf();
+            let amount = self.stream.read(input)?;
+            self.buffers.input_appended(amount);
+            Ok(amount > 0)
+        }
+
+        fn is_open(&mut self) -> bool {
+            self.stream.get_mut().get_mut().is_open()
+        }
+
+        fn is_tls(&self) -> bool {
+            true
+        }
+    }
+}
+
+/// 使用指定 TLS 配置与代理的 ureq Agent，`timeout` 为单次请求的总超时
+#[cfg(feature = "ureq-support")]
+pub fn ureq_agent(
+    tls: Arc<ClientConfig>,
+    proxy: ProxySettings,
+    timeout: Option<std::time::Duration>,
+) -> ureq::Agent {
+    use crate::proxy::ureq_proxy::{ProxyConnector, ProxyResolver};
+    use ureq::unversioned::resolver::DefaultResolver;
+    use ureq::unversioned::transport::{Connector, TcpConnector};
+
+    // 代理由 ProxyConnector 处理，关闭 ureq 自带的环境变量代理
+    let connector =
+        ().chain(ProxyConnector(proxy.clone()))
+            .chain(TcpConnector::default())
+            .chain(ureq_connector::RustlsConfigConnector(tls));
+    let resolver = ProxyResolver {
+        settings: proxy,
+        inner: DefaultResolver::default(),
+    };
+    let config = ureq::Agent::config_builder()
+        .proxy(None)
+        .timeout_global(timeout)
+        .build();
+    ureq::Agent::with_parts(config, connector, resolver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pin() {
+        let hash = [7u8; 32];
+        let pin = format!("{PIN_PREFIX}{}", BASE64.encode(hash));
+        assert_eq!(parse_pin(&pin), Ok(hash));
+        assert!(parse_pin(&BASE64.encode(hash)).is_err());
+        assert!(parse_pin("sha256//AAAA").is_err());
+        assert!(parse_pin("sha256//not base64!").is_err());
+    }
+
+    /// 自签名的 P-256 测试证书 (CN=pin.test)
+    const TEST_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBkzCCATigAwIBAgIUa9QzYVg9mD/c/3SiTH+II04I5R8wCgYIKoZIzj0EAwIw
+EzERMA8GA1UEAwwIcGluLnRlc3QwIBcNMjYxMDE3MjMzNDAxWhgPMjEyNjA5MjMy
+MzM0MDFaMBMxETAPBgNVBAMMCHBpbi50ZXN0MFkwEwYHKoZIzj0CAQYIKoZIzj0D
+AQcDQgAEwNzuMBg5R11cgMxWTmMA2wOrIc8HBoo99xIGyot5D/kasWqfXItAn0Eh
+kYGwlnxSwQ6FcrtGu0SPFZ05O3ZAdKNoMGYwHQYDVR0OBBYEFFzSoJiUNif4W/HO
+Asm1S5IVdpSzMB8GA1UdIwQYMBaAFFzSoJiUNif4W/HOAsm1S5IVdpSzMA8GA1Ud
+EwEB/wQFMAMBAf8wEwYDVR0RBAwwCoIIcGluLnRlc3QwCgYIKoZIzj0EAwIDSQAw
+RgIhAJnnZLsVSyxKzO4ZH0O8q3asiDNMgjc7CYx7sks8WdhmAiEA7k1bNYFpsSLs
+KsUad4JK2dZYsiN2wRVlYfUMmwGFJys=
+-----END CERTIFICATE-----
+";
+    const TEST_PIN: &str = "sha256//NUluX9pjrdGPixgniKUjxrOPla25oPZ09S6WGFdbElk=";
+    const TEST_MESSAGE: &[u8] = b"komari handshake";
+    /// 测试证书私钥对 `TEST_MESSAGE` 的 ECDSA-SHA256 签名
+    const TEST_SIGNATURE: &str = concat!(
+        "MEQCIDY2bQEmZSbZgpeRbX76Srg3kADlHj+rZAuT1qrb1DDXAiA2pX7dGnfoFJ73",
+        "X3iI/e4yM4MJQthc82zSQW4EhWEIwQ=="
+    );
+
+    fn signed(signature: &[u8]) -> DigitallySignedStruct {
+        use rustls::internal::msgs::codec::Codec;
+        let mut bytes = u16::from(SignatureScheme::ECDSA_NISTP256_SHA256)
+            .to_be_bytes()
+            .to_vec();
+        bytes.extend_from_slice(&u16::try_from(signature.len()).unwrap().to_be_bytes());
+        bytes.extend_from_slice(signature);
+        DigitallySignedStruct::read_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_pinned_verifier_checks_signature() {
+        let cert = CertificateDer::from_pem_slice(TEST_CERT.as_bytes()).unwrap();
+        let verifier =
+            PinnedVerifier::new(Arc::new(NoVerification), vec![parse_pin(TEST_PIN).unwrap()]);
+        let name = ServerName::try_from("pin.test").unwrap();
+        assert!(
+            verifier
+                .verify_server_cert(&cert, &[], &name, &[], UnixTime::now())
+                .is_ok()
+        );
+
+        let signature = BASE64.decode(TEST_SIGNATURE).unwrap();
+        assert!(
+            verifier
+                .verify_tls13_signature(TEST_MESSAGE, &cert, &signed(&signature))
+                .is_ok()
+        );
+        // 重放服务端证书但无法给出有效签名时拒绝握手
+        let mut forged = signature.clone();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert!(
+            verifier
+                .verify_tls13_signature(TEST_MESSAGE, &cert, &signed(&forged))
+                .is_err()
+        );
+        assert!(
+            verifier
+                .verify_tls12_signature(b"other message", &cert, &signed(&signature))
+                .is_err()
+        );
+        assert!(
+            !verifier
+                .supported_verify_schemes()
+                .contains(&SignatureScheme::RSA_PKCS1_SHA1)
+        );
+    }
 }
@@ -0,0 +1,289 @@
+use crate::config::Config;
+use crate::data_struct::RealTimeInfo;
+use crate::get_info::network::filter::InterfaceFilter;
+use crate::get_info::network::interface_counters;
+use crate::get_info::network::traffic_control::{apply_adjustment, take_pending};
+use crate::get_info::network::traffic_stats::TrafficStats;
+use crate::quota::check_quota;
+use crate::shutdown::Shutdown;
+use log::{debug, error, info, trace, warn};
+use miniserde::json;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sysinfo::{CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, Networks, RefreshKind};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+/// 一次 `RealTimeInfo` 采样
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// 采样时间 (Unix 毫秒时间戳)
+    pub timestamp: u64,
+    /// 序列化后的 `RealTimeInfo`
+    pub json: String,
+}
+
+impl Sample {
+    /// 上报的 JSON，附带采样时间 `updated_at` (RFC 3339，UTC)
+    ///
+    /// 回放离线样本时主端据此记录样本的原始时间，而不是将旧数据当作当前数据。
+    pub fn payload(&self) -> String {
+        let nanos = i128::from(self.timestamp) * 1_000_000;
+        let updated_at = OffsetDateTime::from_unix_timestamp_nanos(nanos)
+            .ok()
+            .and_then(|time| time.format(&Rfc3339).ok());
+        match (updated_at, self.json.strip_prefix('{')) {
+            (Some(updated_at), Some(rest)) if rest.trim_start().starts_with('}') => {
+                format!("{{\"updated_at\":\"{updated_at}\"{rest}")
+            }
+            (Some(updated_at), Some(rest)) => format!("{{\"updated_at\":\"{updated_at}\",{rest}"),
+            _ => self.json.clone(),
+        }
+    }
+}
+
+/// 一个上报端的样本通道
+pub struct SampleSink {
+    /// 日志前缀，与上报端一致
+    label: String,
+    tx: Sender<Sample>,
+    /// 上报端处理不及时连续丢弃的样本数
+    dropped: u64,
+}
+
+impl SampleSink {
+    pub fn new(server_name: Option<&str>, tx: Sender<Sample>) -> Self {
+        Self {
+            label: server_name
+                .map(|name| format!("[{name}] "))
+                .unwrap_or_default(),
+            tx,
+            dropped: 0,
+        }
+    }
+
+    /// 不等待地发送样本，通道已满时丢弃该上报端的这一样本，返回上报端是否仍在运行
+    ///
+    /// 某个主端卡住时不会拖慢采样，也不会影响其他主端。
+    fn offer(&mut self, sample: Sample) -> bool {
+        match self.tx.try_send(sample) {
+            Ok(()) => {
+                if self.dropped > 0 {
+                    info!(
+                        "{}上报端已恢复接收样本，期间丢弃 {} 条样本",
+                        self.label, self.dropped
+                    );
+                    self.dropped = 0;
+                }
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                if self.dropped == 1 {
+                    warn!("{}上报端处理不及时，丢弃样本", self.label);
+                }
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// 将样本发送给全部上报端，移除已退出的上报端
+///
+/// 某个上报端通道已满或已关闭不影响其他上报端收到样本。
+fn fan_out(sinks: &mut Vec<SampleSink>, sample: &Sample) {
+    sinks.retain_mut(|sink| sink.offer(sample.clone()));
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// 采样循环，与 WebSocket 连接状态无关，断线期间同样持续采样
+///
+/// 每个样本发送给全部上报端 (每个主端一个)，上报端处理不及时则丢弃该上报端的样本，
+/// 全部上报端退出后停止采样。
+/// 收到退出信号后保存流量统计并退出，随后上报端会在发送完剩余样本后关闭连接
+pub async fn run_sampler(
+    mut config_rx: watch::Receiver<Config>,
+    data_dir: PathBuf,
+    mut sinks: Vec<SampleSink>,
+    mut shutdown: Shutdown,
+) {
+    let mut sysinfo_sys = sysinfo::System::new();
+    let mut networks = Networks::new_with_refreshed_list();
+    let mut disks = Disks::new();
+    let mut config = config_rx.borrow_and_update().clone();
+    let mut interface_filter = InterfaceFilter::from_config(&config);
+
+    // 初始化流量统计
+    let mut traffic_stats = TrafficStats::load_or_create(
+        &data_dir,
+        config.billing_day,
+        config.billing_timezone.clone(),
+    );
+
+    info!(
+        "本周期计费流量 ({}): {}",
+        config.traffic_billing_mode.as_str(),
+        traffic_stats.usage(config.traffic_billing_mode, config.traffic_quota)
+    );
+
+    // 保存计数器，用于定期持久化
+    let mut save_counter: u32 = 0;
+
+    loop {
+        let start_time = tokio::time::Instant::now();
+        sysinfo_sys.refresh_specifics(
+            RefreshKind::nothing()
+                .with_cpu(CpuRefreshKind::everything().without_frequency())
+                .with_memory(MemoryRefreshKind::everything()),
+        );
+        networks.refresh(true);
+        disks.refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
+        let real_time = RealTimeInfo::build(
+            &sysinfo_sys,
+            &networks,
+            &interface_filter,
+            &disks,
+            &mut traffic_stats,
+            config.realtime_info_interval,
+            config.fake,
+        );
+
+        // 命令行提交的手动调整 (traffic reset/set/add)
+        if let Some(adjustment) = take_pending(&data_dir) {
+            apply_adjustment(&mut traffic_stats, &adjustment, &config);
+            traffic_stats.save();
+            info!(
+                "已应用流量调整 ({adjustment}): {}",
+                traffic_stats.usage(config.traffic_billing_mode, config.traffic_quota)
+            );
+        }
+
+        let usage = traffic_stats.usage(config.traffic_billing_mode, config.traffic_quota);
+        if check_quota(&mut traffic_stats, usage, &config.quota_actions) {
+            traffic_stats.save();
+        }
+
+        // 每 60 次上报保存一次流量统计（默认间隔下约 1 分钟）
+        save_counter += 1;
+        if save_counter >= 60 {
+            traffic_stats.save();
+            save_counter = 0;
+            debug!("本周期计费流量: {usage}");
+            debug!("各网卡流量: {}", traffic_stats.interface_summary());
+        }
+
+        let sample = Sample {
+            timestamp: unix_millis(),
+            json: json::to_string(&real_time),
+        };
+        fan_out(&mut sinks, &sample);
+        if sinks.is_empty() {
+            error!("上报任务已退出，停止采样");
+            traffic_stats.save();
+            return;
+        }
+        trace!("RealTime 采样完成");
+
+        let end_time = start_time.elapsed();
+
+        let delay = Duration::from_millis({
+            let end = u64::try_from(end_time.as_millis()).unwrap_or(0);
+            config.realtime_info_interval.saturating_sub(end)
+        });
+
+        tokio::select! {
+            () = sleep(delay) => {}
+            // 热重载: 采样间隔、网卡筛选、配额等配置从下一次采样起生效
+            Ok(()) = config_rx.changed() => {
+                config = config_rx.borrow_and_update().clone();
+                interface_filter = InterfaceFilter::from_config(&config);
+                traffic_stats.rebaseline(&interface_counters(&networks, &interface_filter));
+            }
+            () = shutdown.recv() => {
+                traffic_stats.save();
+                info!("流量统计已保存，采样结束");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_timestamp() {
+        let sample = Sample {
+            timestamp: 1_700_000_000_123,
+            json: "{\"uptime\":1}".to_string(),
+        };
+        assert_eq!(
+            sample.payload(),
+            "{\"updated_at\":\"2023-11-14T22:13:20.123Z\",\"uptime\":1}"
+        );
+    }
+
+    #[test]
+    fn test_sink_drops_when_full() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let mut sink = SampleSink::new(Some("a"), tx);
+        let sample = |timestamp| Sample {
+            timestamp,
+            json: "{}".to_string(),
+        };
+        assert!(sink.offer(sample(1)));
+        assert!(sink.offer(sample(2)));
+        assert_eq!(sink.dropped, 1);
+        assert_eq!(rx.try_recv().unwrap().timestamp, 1);
+        assert!(sink.offer(sample(3)));
+        assert_eq!(sink.dropped, 0);
+        drop(rx);
+        assert!(!sink.offer(sample(4)));
+    }
+
+    #[test]
+    fn test_fan_out_to_every_server() {
+        let sample = |timestamp| Sample {
+            timestamp,
+            json: "{}".to_string(),
+        };
+        let (tx_a, mut rx_a) = tokio::sync::mpsc::channel(8);
+        let (tx_b, mut rx_b) = tokio::sync::mpsc::channel(1);
+        let (tx_c, rx_c) = tokio::sync::mpsc::channel(8);
+        let (tx_d, mut rx_d) = tokio::sync::mpsc::channel(8);
+        let mut sinks = vec![
+            SampleSink::new(None, tx_a),
+            SampleSink::new(Some("b"), tx_b),
+            SampleSink::new(Some("c"), tx_c),
+            SampleSink::new(Some("d"), tx_d),
+        ];
+
+        fan_out(&mut sinks, &sample(1));
+        // b 已满，c 已退出，其余上报端照常收到样本
+        drop(rx_c);
+        fan_out(&mut sinks, &sample(2));
+        fan_out(&mut sinks, &sample(3));
+        assert_eq!(sinks.len(), 3);
+
+        for rx in [&mut rx_a, &mut rx_d] {
+            let received: Vec<u64> = std::iter::from_fn(|| rx.try_recv().ok())
+                .map(|sample| sample.timestamp)
+                .collect();
+            assert_eq!(received, [1, 2, 3]);
+        }
+        assert_eq!(rx_b.try_recv().unwrap().timestamp, 1);
+        assert!(rx_b.try_recv().is_err());
+        assert_eq!(sinks[1].dropped, 2);
+    }
+}
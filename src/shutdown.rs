@@ -0,0 +1,155 @@
+use log::{info, warn};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::time::timeout;
+
+/// 退出流程中等待各任务收尾的最长时间
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 退出信号的发送端，持有所有在途任务的计数
+pub struct ShutdownController {
+    notify: watch::Sender<bool>,
+    tasks_tx: mpsc::Sender<()>,
+    tasks_rx: mpsc::Receiver<()>,
+}
+
+/// 退出信号的接收端，可在各任务间克隆传递
+#[derive(Clone)]
+pub struct Shutdown {
+    notify: watch::Receiver<bool>,
+    tasks: mpsc::WeakSender<()>,
+}
+
+/// 在途任务的守卫，全部守卫释放后退出流程才会结束等待
+pub struct TaskGuard {
+    _tasks: mpsc::Sender<()>,
+}
+
+impl ShutdownController {
+    pub fn new() -> Self {
+        let (notify, _) = watch::channel(false);
+        let (tasks_tx, tasks_rx) = mpsc::channel(1);
+        Self {
+            notify,
+            tasks_tx,
+            tasks_rx,
+        }
+    }
+
+    pub fn handle(&self) -> Shutdown {
+        Shutdown {
+            notify: self.notify.subscribe(),
+            tasks: self.tasks_tx.downgrade(),
+        }
+    }
+
+    /// 通知所有任务开始退出
+    pub fn trigger(&self) {
+        self.notify.send_replace(true);
+    }
+
+    /// 等待所有在途任务 (PTY 会话、Exec 回调) 结束，最多等待 `limit`
+    pub async fn wait_tasks(self, limit: Duration) {
+        let Self {
+            tasks_tx,
+            mut tasks_rx,
+            ..
+        } = self;
+        drop(tasks_tx);
+
+        if timeout(limit, tasks_rx.recv()).await.is_err() {
+            warn!("等待在途任务结束超时，强制退出");
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.notify.borrow()
+    }
+
+    /// 等待退出信号
+    pub async fn recv(&mut self) {
+        let _ = self.notify.wait_for(|triggered| *triggered).await;
+    }
+
+    /// 登记一个需要在退出前等待完成的任务，已开始退出时返回 None
+    pub fn track(&self) -> Option<TaskGuard> {
+        if self.is_triggered() {
+            return None;
+        }
+        self.tasks
+            .upgrade()
+            .map(|tasks| TaskGuard { _tasks: tasks })
+    }
+}
+
+/// 等待 SIGINT / SIGTERM (Windows 下为 Ctrl+C)
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                warn!("无法监听 SIGTERM: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+                info!("收到 SIGINT，开始退出");
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => info!("收到 SIGINT，开始退出"),
+            _ = terminate.recv() => info!("收到 SIGTERM，开始退出"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("收到 Ctrl+C，开始退出");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_guarded_tasks() {
+        let controller = ShutdownController::new();
+        let mut shutdown = controller.handle();
+        let guard = shutdown.track().unwrap();
+        let finished = Arc::new(AtomicBool::new(false));
+        let task_finished = finished.clone();
+        tokio::spawn(async move {
+            shutdown.recv().await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            task_finished.store(true, Ordering::SeqCst);
+            drop(guard);
+        });
+
+        controller.trigger();
+        // 开始退出后不再登记新任务
+        assert!(controller.handle().track().is_none());
+        let started = Instant::now();
+        controller.wait_tasks(SHUTDOWN_TIMEOUT).await;
+        assert!(finished.load(Ordering::SeqCst));
+        assert!(started.elapsed() < SHUTDOWN_TIMEOUT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_tasks_timeout() {
+        let controller = ShutdownController::new();
+        // 任务卡住，守卫一直不释放
+        let _guard = controller.handle().track().unwrap();
+        controller.trigger();
+        let started = Instant::now();
+        controller.wait_tasks(SHUTDOWN_TIMEOUT).await;
+        assert!(started.elapsed() >= SHUTDOWN_TIMEOUT);
+        assert!(started.elapsed() < SHUTDOWN_TIMEOUT + Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::config::Config;
+use log::{info, warn};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// 需要随实例目录迁移的旧版状态文件
+const LEGACY_STATE_FILES: [&str; 3] = [
+    "traffic_stats.dat",
+    "traffic_stats.dat.bak",
+    "offline_buffer.dat",
+];
+
+/// 特权运行时的系统状态目录
+#[cfg(target_os = "windows")]
+fn system_data_dir() -> PathBuf {
+    std::env::var("PROGRAMDATA").map_or_else(
+        |_| PathBuf::from("C:\\ProgramData\\komari-monitor"),
+        |p| PathBuf::from(p).join("komari-monitor"),
+    )
+}
+
+/// 特权运行时的系统状态目录
+#[cfg(not(target_os = "windows"))]
+fn system_data_dir() -> PathBuf {
+    PathBuf::from("/var/lib/komari-monitor")
+}
+
+#[cfg(target_os = "linux")]
+fn is_privileged() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_privileged() -> bool {
+    std::env::var("USER").unwrap_or_default() == "root"
+}
+
+#[cfg(not(unix))]
+fn is_privileged() -> bool {
+    true
+}
+
+/// 非特权运行时的用户状态目录 (`$XDG_STATE_HOME` 或 `~/.local/state`)
+fn user_data_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .filter(|p| !p.is_empty())
+                .map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(base.join("komari-monitor"))
+}
+
+/// 数据根目录: `data_dir` 配置 > systemd 的 `$STATE_DIRECTORY` > 系统目录 (特权) / XDG 目录 (非特权)
+fn base_data_dir(config: &Config) -> PathBuf {
+    if let Some(dir) = &config.data_dir {
+        return PathBuf::from(dir);
+    }
+
+    // systemd 可能以冒号分隔传入多个目录，取第一个
+    if let Some(dir) = std::env::var("STATE_DIRECTORY")
+        .ok()
+        .and_then(|dirs| dirs.split(':').next().map(str::to_string))
+        .filter(|dir| !dir.is_empty())
+    {
+        return PathBuf::from(dir);
+    }
+
+    if is_privileged() {
+        system_data_dir()
+    } else {
+        user_data_dir().unwrap_or_else(system_data_dir)
+    }
+}
+
+/// 实例名: 优先使用 `instance` 配置，否则为 `default`
+///
+/// 同机运行多个 Agent 时需分别设置 `instance`，避免共用状态。
+pub fn instance_name(config: &Config) -> String {
+    match &config.instance {
+        Some(instance) => instance
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect(),
+        None => DEFAULT_INSTANCE.to_string(),
+    }
+}
+
+/// 未设置 `instance` 时的实例名
+const DEFAULT_INSTANCE: &str = "default";
+
+/// 旧版未设置 `instance` 时由 token 派生的实例目录 (`token-<crc32>`)
+fn legacy_token_dir(base: &Path, config: &Config) -> PathBuf {
+    base.join(format!("token-{:08x}", crc32(config.token.as_bytes())))
+}
+
+/// 旧版实例目录由 token 派生，更换 token 即丢失状态，现默认使用 `default`，
+/// 首次启动时将旧目录改名沿用
+fn migrate_token_dir(base: &Path, dir: &Path, config: &Config) {
+    let legacy = legacy_token_dir(base, config);
+    if !legacy.is_dir() {
+        return;
+    }
+    if dir.exists() {
+        warn!(
+            "旧版数据目录 {} 未被使用 ({} 已存在)，如需沿用请设置 instance = \"{}\"",
+            legacy.display(),
+            dir.display(),
+            legacy.file_name().unwrap_or_default().to_string_lossy()
+        );
+        return;
+    }
+    match fs::rename(&legacy, dir) {
+        Ok(()) => info!("已将数据目录 {} 迁移到 {}", legacy.display(), dir.display()),
+        Err(e) => warn!("迁移旧版数据目录 {} 失败: {e}", legacy.display()),
+    }
+}
+
+/// 当前实例的数据目录路径，不创建目录也不迁移旧版状态，供只读的命令使用
+///
+/// 旧版由 token 派生的实例目录尚未迁移时返回旧目录。
+pub fn instance_dir(config: &Config) -> PathBuf {
+    let base = base_data_dir(config);
+    let dir = base.join(instance_name(config));
+    if config.instance.is_none() && !dir.exists() {
+        let legacy = legacy_token_dir(&base, config);
+        if legacy.is_dir() {
+            return legacy;
+        }
+    }
+    dir
+}
+
+/// 当前实例的数据目录，所有持久化状态都保存在此目录下
+///
+/// 首次使用时会把旧版直接存放在根目录下的状态文件迁移进来。
+pub fn instance_data_dir(config: &Config) -> PathBuf {
+    let base = base_data_dir(config);
+    let dir = base.join(instance_name(config));
+
+    if config.instance.is_none() {
+        migrate_token_dir(&base, &dir, config);
+    }
+    if let Err(e) = fs::create_dir_all(&dir) {
+        warn!("无法创建数据目录 {}: {e}", dir.display());
+        return dir;
+    }
+
+    let mut legacy_dirs = vec![base];
+    if is_privileged() && !legacy_dirs.contains(&system_data_dir()) {
+        legacy_dirs.push(system_data_dir());
+    }
+    if !dir.join(LEGACY_STATE_FILES[0]).exists()
+        && let Some(legacy) = legacy_dirs
+            .iter()
+            .find(|d| d.join(LEGACY_STATE_FILES[0]).is_file())
+    {
+        for name in LEGACY_STATE_FILES {
+            let from = legacy.join(name);
+            if from.is_file()
+                && let Err(e) = move_file(&from, &dir.join(name))
+            {
+                warn!("迁移旧版状态文件 {} 失败: {e}", from.display());
+            }
+        }
+        info!(
+            "已将旧版状态文件从 {} 迁移到 {}",
+            legacy.display(),
+            dir.display()
+        );
+    }
+
+    dir
+}
+
+/// 移动文件，跨文件系统时退化为复制后删除
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// 原子写入文件: 先写入同目录下的临时文件并 fsync，再 rename 覆盖目标文件
+///
+/// 任意时刻断电，目标文件要么是旧内容，要么是完整的新内容。临时文件创建时即为仅所有者可读写，
+/// 目标文件已存在时沿用其权限。
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    if let Some(parent) = parent {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    {
+        // 残留的临时文件可能权限过宽，删除后重新创建
+        let _ = fs::remove_file(&tmp_path);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp_path)?;
+        // 保留目标文件原有的权限 (如 0600 的配置文件)
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    // 同步目录项，保证 rename 本身也已落盘
+    #[cfg(unix)]
+    if let Some(parent) = parent
+        && let Ok(dir) = fs::File::open(parent)
+    {
+        let _ = dir.sync_all();
+    }
+
+    Ok(())
+}
+
+/// CRC-32 (IEEE 802.3)，用于校验持久化文件是否完整
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let path = std::env::temp_dir().join(format!("komari-atomic-{}.dat", std::process::id()));
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_token_dir_migration() {
+        let base = std::env::temp_dir().join(format!("komari-instance-{}", std::process::id()));
+        let config = Config {
+            data_dir: Some(base.to_string_lossy().into_owned()),
+            token: "secret".to_string(),
+            ..Config::default()
+        };
+        let legacy = base.join(format!("token-{:08x}", crc32(b"secret")));
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("traffic_stats.dat"), "stats").unwrap();
+
+        let dir = instance_data_dir(&config);
+        assert_eq!(dir, base.join("default"));
+        assert!(!legacy.exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("traffic_stats.dat")).unwrap(),
+            "stats"
+        );
+        let _ = fs::remove_dir_all(&base);
+    }
+}
@@ -0,0 +1,574 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use time::{Date, Month};
+
+/// Unix 纪元 (1970-01-01) 对应的儒略日
+const UNIX_EPOCH_JULIAN_DAY: i64 = 2_440_588;
+
+/// 计费周期使用的时区
+#[derive(Debug, Clone)]
+pub enum BillingTimezone {
+    /// 固定 UTC 偏移 (秒)
+    Fixed(i32),
+    /// IANA 时区 (读取系统 zoneinfo) 或 POSIX TZ 规则，支持夏令时
+    Zone(Arc<Zone>),
+}
+
+impl Default for BillingTimezone {
+    /// 与旧版本保持一致，默认使用 UTC+8
+    fn default() -> Self {
+        Self::Fixed(8 * 3600)
+    }
+}
+
+impl Display for BillingTimezone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed(offset) => {
+                let sign = if *offset < 0 { '-' } else { '+' };
+                let abs = offset.unsigned_abs();
+                write!(f, "{sign}{:02}:{:02}", abs / 3600, abs % 3600 / 60)
+            }
+            Self::Zone(zone) => f.write_str(&zone.name),
+        }
+    }
+}
+
+impl BillingTimezone {
+    /// 解析时区配置
+    ///
+    /// 支持 `UTC`、`+08:00` / `-0530` / `UTC+8` 形式的固定偏移 (按常规含义，东区为正)，
+    /// `Asia/Shanghai` 形式的 IANA 时区名，以及 `CET-1CEST,M3.5.0,M10.5.0/3` 形式的 POSIX TZ 规则。
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("时区不能为空".to_string());
+        }
+
+        for prefix in ["UTC", "GMT"] {
+            if let Some(rest) = strip_prefix_ignore_case(value, prefix) {
+                if rest.is_empty() {
+                    return Ok(Self::Fixed(0));
+                }
+                if rest.starts_with(['+', '-']) {
+                    return parse_fixed_offset(rest).map(Self::Fixed);
+                }
+            }
+        }
+        if value.starts_with(['+', '-']) {
+            return parse_fixed_offset(value).map(Self::Fixed);
+        }
+
+        let iana_error = match Zone::load_iana(value) {
+            Ok(zone) => return Ok(Self::Zone(Arc::new(zone))),
+            Err(e) => e,
+        };
+
+        // 不含规则部分的值更可能是写错的 IANA 时区名，返回读取 zoneinfo 的错误
+        let rule = PosixRule::parse(value).map_err(|e| {
+            if value.contains(',') {
+                format!("无法识别的时区 {value}: {e}")
+            } else {
+                iana_error
+            }
+        })?;
+        Ok(Self::Zone(Arc::new(Zone {
+            name: value.to_string(),
+            transitions: Vec::new(),
+            initial: rule.std_offset,
+            rule: Some(rule),
+        })))
+    }
+
+    /// 指定 UTC 时刻 (Unix 秒) 在该时区的偏移 (秒)
+    pub fn offset_at(&self, unix_secs: i64) -> i32 {
+        match self {
+            Self::Fixed(offset) => *offset,
+            Self::Zone(zone) => zone.offset_at(unix_secs),
+        }
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = value.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &value[prefix.len()..])
+}
+
+/// 解析 `+8` / `+08:00` / `-0530` 形式的偏移，返回秒数 (东区为正)
+fn parse_fixed_offset(value: &str) -> Result<i32, String> {
+    let invalid = || format!("无效的时区偏移: {value}");
+    let (sign, digits) = match value.split_at(1) {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return Err(invalid()),
+    };
+
+    let (hours, minutes) = if let Some((h, m)) = digits.split_once(':') {
+        (h, m)
+    } else if digits.len() == 4 {
+        digits.split_at(2)
+    } else {
+        (digits, "0")
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 14 || minutes >= 60 {
+        return Err(invalid());
+    }
+
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+/// 带转换表的时区
+#[derive(Debug)]
+pub struct Zone {
+    name: String,
+    /// 转换表: (UTC 时刻, 该时刻起生效的偏移)，按时间升序
+    transitions: Vec<(i64, i32)>,
+    /// 第一个转换之前的偏移
+    initial: i32,
+    /// 最后一个转换之后使用的 POSIX 规则
+    rule: Option<PosixRule>,
+}
+
+impl Zone {
+    fn offset_at(&self, unix_secs: i64) -> i32 {
+        let index = self.transitions.partition_point(|(at, _)| *at <= unix_secs);
+        if index == 0 {
+            return match (&self.rule, self.transitions.is_empty()) {
+                (Some(rule), true) => rule.offset_at(unix_secs),
+                _ => self.initial,
+            };
+        }
+        match &self.rule {
+            Some(rule) if index == self.transitions.len() => rule.offset_at(unix_secs),
+            _ => self.transitions[index - 1].1,
+        }
+    }
+
+    /// 从系统 zoneinfo 数据库 (`$TZDIR` 或 `/usr/share/zoneinfo`) 加载 IANA 时区
+    fn load_iana(name: &str) -> Result<Self, String> {
+        if name
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err(format!("无效的时区名: {name}"));
+        }
+        let dir = std::env::var_os("TZDIR")
+            .map_or_else(|| PathBuf::from("/usr/share/zoneinfo"), PathBuf::from);
+        let data = fs::read(dir.join(name)).map_err(|e| format!("无法读取时区 {name}: {e}"))?;
+        let mut zone = parse_tzif(&data).map_err(|e| format!("时区文件 {name} 无效: {e}"))?;
+        zone.name = name.to_string();
+        Ok(zone)
+    }
+}
+
+/// 解析 `TZif` 文件 (RFC 8536)，优先使用 v2+ 的 64 位数据块
+fn parse_tzif(data: &[u8]) -> Result<Zone, String> {
+    struct Header {
+        isutcnt: usize,
+        isstdcnt: usize,
+        leapcnt: usize,
+        timecnt: usize,
+        typecnt: usize,
+        charcnt: usize,
+    }
+
+    fn read_header(data: &[u8]) -> Result<(u8, Header), String> {
+        if data.len() < 44 || &data[..4] != b"TZif" {
+            return Err("不是 TZif 文件".to_string());
+        }
+        let count = |i: usize| {
+            let bytes = [
+                data[20 + i * 4],
+                data[21 + i * 4],
+                data[22 + i * 4],
+                data[23 + i * 4],
+            ];
+            u32::from_be_bytes(bytes) as usize
+        };
+        Ok((
+            data[4],
+            Header {
+                isutcnt: count(0),
+                isstdcnt: count(1),
+                leapcnt: count(2),
+                timecnt: count(3),
+                typecnt: count(4),
+                charcnt: count(5),
+            },
+        ))
+    }
+
+    let (version, header) = read_header(data)?;
+    let v1_len = header.timecnt * 5
+        + header.typecnt * 6
+        + header.charcnt
+        + header.leapcnt * 8
+        + header.isstdcnt
+        + header.isutcnt;
+
+    let (body, header, time_size) = if version >= b'2' {
+        let rest = data.get(44 + v1_len..).ok_or("文件被截断")?;
+        let (_, header) = read_header(rest)?;
+        (&rest[44..], header, 8)
+    } else {
+        (&data[44..], header, 4)
+    };
+
+    let times_len = header.timecnt * time_size;
+    let ttinfo_start = times_len + header.timecnt;
+    let data_len = ttinfo_start
+        + header.typecnt * 6
+        + header.charcnt
+        + header.leapcnt * (time_size + 4)
+        + header.isstdcnt
+        + header.isutcnt;
+    if body.len() < data_len || header.typecnt == 0 {
+        return Err("文件被截断".to_string());
+    }
+
+    let offsets: Vec<i32> = (0..header.typecnt)
+        .map(|i| {
+            let at = ttinfo_start + i * 6;
+            i32::from_be_bytes([body[at], body[at + 1], body[at + 2], body[at + 3]])
+        })
+        .collect();
+
+    let mut transitions = Vec::with_capacity(header.timecnt);
+    for i in 0..header.timecnt {
+        let at = i * time_size;
+        let time = if time_size == 8 {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&body[at..at + 8]);
+            i64::from_be_bytes(bytes)
+        } else {
+            i64::from(i32::from_be_bytes([
+                body[at],
+                body[at + 1],
+                body[at + 2],
+                body[at + 3],
+            ]))
+        };
+        let index = usize::from(body[times_len + i]);
+        let offset = *offsets.get(index).ok_or("本地时间类型索引越界")?;
+        transitions.push((time, offset));
+    }
+
+    // v2+ 文件末尾的 POSIX TZ 规则用于最后一个转换之后的时刻
+    let rule = if version >= b'2' {
+        std::str::from_utf8(&body[data_len..])
+            .ok()
+            .and_then(|footer| footer.trim_matches('\n').lines().next())
+            .filter(|footer| !footer.is_empty())
+            .map(PosixRule::parse)
+            .transpose()?
+    } else {
+        None
+    };
+
+    Ok(Zone {
+        name: String::new(),
+        transitions,
+        initial: offsets[0],
+        rule,
+    })
+}
+
+/// POSIX TZ 规则，如 `CET-1CEST,M3.5.0,M10.5.0/3`
+#[derive(Debug)]
+struct PosixRule {
+    /// 标准时间偏移 (秒，东区为正)
+    std_offset: i32,
+    dst: Option<DstRule>,
+}
+
+#[derive(Debug)]
+struct DstRule {
+    /// 夏令时偏移 (秒，东区为正)
+    offset: i32,
+    /// 夏令时开始日期与当地 (标准) 时间
+    start: (RuleDate, i32),
+    /// 夏令时结束日期与当地 (夏令) 时间
+    end: (RuleDate, i32),
+}
+
+#[derive(Debug)]
+enum RuleDate {
+    /// `Jn`: 一年中的第 n 天 (1..=365)，不计 2 月 29 日
+    JulianNoLeap(u16),
+    /// `n`: 一年中的第 n 天 (0..=365)，计入 2 月 29 日
+    Julian(u16),
+    /// `Mm.w.d`: m 月第 w 个星期 d (w = 5 表示最后一个，d = 0 表示星期日)
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+}
+
+impl PosixRule {
+    fn parse(value: &str) -> Result<Self, String> {
+        let mut rest = value;
+        take_name(&mut rest)?;
+        let std_offset = -take_time(&mut rest)?;
+
+        if rest.is_empty() {
+            return Ok(Self {
+                std_offset,
+                dst: None,
+            });
+        }
+
+        take_name(&mut rest)?;
+        let dst_offset = if rest.is_empty() || rest.starts_with(',') {
+            std_offset + 3600
+        } else {
+            -take_time(&mut rest)?
+        };
+
+        // 未给出规则时使用美国规则 (与 glibc 一致)
+        let rules = rest.strip_prefix(',').unwrap_or("M3.2.0,M11.1.0");
+        let (start, end) = rules.split_once(',').ok_or("缺少夏令时结束规则")?;
+
+        Ok(Self {
+            std_offset,
+            dst: Some(DstRule {
+                offset: dst_offset,
+                start: parse_rule_date(start)?,
+                end: parse_rule_date(end)?,
+            }),
+        })
+    }
+
+    fn offset_at(&self, unix_secs: i64) -> i32 {
+        let Some(dst) = &self.dst else {
+            return self.std_offset;
+        };
+
+        let local_days = (unix_secs + i64::from(self.std_offset)).div_euclid(86400);
+        let year = date_from_unix_days(local_days).year();
+
+        let start = rule_local_secs(&dst.start.0, year) + i64::from(dst.start.1)
+            - i64::from(self.std_offset);
+        let end = rule_local_secs(&dst.end.0, year) + i64::from(dst.end.1) - i64::from(dst.offset);
+
+        let in_dst = if start < end {
+            start <= unix_secs && unix_secs < end
+        } else {
+            // 南半球: 夏令时跨年
+            !(end <= unix_secs && unix_secs < start)
+        };
+
+        if in_dst { dst.offset } else { self.std_offset }
+    }
+}
+
+/// 读取时区缩写: 字母序列或 `<...>` 形式
+fn take_name(rest: &mut &str) -> Result<(), String> {
+    if let Some(quoted) = rest.strip_prefix('<') {
+        let end = quoted.find('>').ok_or("时区缩写缺少 '>'")?;
+        *rest = &quoted[end + 1..];
+        return Ok(());
+    }
+    let len = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    if len < 3 {
+        return Err("时区缩写至少需要 3 个字母".to_string());
+    }
+    *rest = &rest[len..];
+    Ok(())
+}
+
+/// 读取 `[+-]hh[:mm[:ss]]` 形式的时间，返回秒数
+fn take_time(rest: &mut &str) -> Result<i32, String> {
+    let len = rest
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | ':')))
+        .unwrap_or(rest.len());
+    let (time, remain) = rest.split_at(len);
+    *rest = remain;
+    parse_rule_time(time)
+}
+
+fn parse_rule_time(time: &str) -> Result<i32, String> {
+    let invalid = || format!("无效的时间: {time}");
+    let (sign, digits) = if let Some(d) = time.strip_prefix('-') {
+        (-1, d)
+    } else {
+        (1, time.strip_prefix('+').unwrap_or(time))
+    };
+    let mut secs = 0;
+    for (i, part) in digits.split(':').enumerate() {
+        let value: i32 = part.parse().map_err(|_| invalid())?;
+        secs += match i {
+            0 if value <= 167 => value * 3600,
+            1 if value < 60 => value * 60,
+            2 if value < 60 => value,
+            _ => return Err(invalid()),
+        };
+    }
+    Ok(sign * secs)
+}
+
+fn parse_rule_date(rule: &str) -> Result<(RuleDate, i32), String> {
+    let invalid = || format!("无效的夏令时规则: {rule}");
+    let (date, time) = match rule.split_once('/') {
+        Some((date, time)) => (date, parse_rule_time(time)?),
+        None => (rule, 2 * 3600),
+    };
+
+    let date = if let Some(mwd) = date.strip_prefix('M') {
+        let mut parts = mwd.split('.').map(str::parse::<u8>);
+        let (Some(Ok(month)), Some(Ok(week)), Some(Ok(weekday)), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+            return Err(invalid());
+        }
+        RuleDate::MonthWeekDay {
+            month,
+            week,
+            weekday,
+        }
+    } else if let Some(day) = date.strip_prefix('J') {
+        let day: u16 = day.parse().map_err(|_| invalid())?;
+        if !(1..=365).contains(&day) {
+            return Err(invalid());
+        }
+        RuleDate::JulianNoLeap(day)
+    } else {
+        let day: u16 = date.parse().map_err(|_| invalid())?;
+        if day > 365 {
+            return Err(invalid());
+        }
+        RuleDate::Julian(day)
+    };
+
+    Ok((date, time))
+}
+
+fn date_from_unix_days(days: i64) -> Date {
+    let julian = i32::try_from(days + UNIX_EPOCH_JULIAN_DAY).unwrap_or(i32::MAX);
+    Date::from_julian_day(julian).unwrap_or(Date::MAX)
+}
+
+fn unix_days(date: Date) -> i64 {
+    i64::from(date.to_julian_day()) - UNIX_EPOCH_JULIAN_DAY
+}
+
+/// 规则日期当天 00:00 (当地时间) 距 Unix 纪元的秒数
+fn rule_local_secs(rule: &RuleDate, year: i32) -> i64 {
+    let date = match *rule {
+        RuleDate::JulianNoLeap(day) => {
+            let leap = time::util::is_leap_year(year);
+            let ordinal = if leap && day >= 60 { day + 1 } else { day };
+            Date::from_ordinal_date(year, ordinal)
+        }
+        RuleDate::Julian(day) => Date::from_ordinal_date(year, day + 1),
+        RuleDate::MonthWeekDay {
+            month,
+            week,
+            weekday,
+        } => {
+            let month = Month::try_from(month).unwrap_or(Month::January);
+            Date::from_calendar_date(year, month, 1).and_then(|first| {
+                let first_weekday = first.weekday().number_days_from_sunday();
+                let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+                if day > month.length(year) {
+                    day -= 7;
+                }
+                Date::from_calendar_date(year, month, day)
+            })
+        }
+    };
+    // 非法日期 (如平年的第 366 天) 退化为年末
+    let date = date.unwrap_or_else(|_| {
+        Date::from_calendar_date(year, Month::December, 31).unwrap_or(Date::MAX)
+    });
+    unix_days(date) * 86400
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-MM-DD hh:mm:ss UTC 对应的 Unix 秒
+    fn utc(month: u8, day: u8, hour: i64, minute: i64) -> i64 {
+        let date = Date::from_calendar_date(2025, Month::try_from(month).unwrap(), day).unwrap();
+        unix_days(date) * 86400 + hour * 3600 + minute * 60
+    }
+
+    #[test]
+    fn test_parse_fixed_offset() {
+        assert_eq!(BillingTimezone::parse("UTC").unwrap().offset_at(0), 0);
+        assert_eq!(
+            BillingTimezone::parse("+08:00").unwrap().offset_at(0),
+            8 * 3600
+        );
+        assert_eq!(
+            BillingTimezone::parse("UTC+8").unwrap().offset_at(0),
+            8 * 3600
+        );
+        assert_eq!(
+            BillingTimezone::parse("-0530").unwrap().offset_at(0),
+            -19800
+        );
+        assert_eq!(
+            BillingTimezone::parse("gmt-5").unwrap().offset_at(0),
+            -5 * 3600
+        );
+        assert_eq!(
+            BillingTimezone::parse("+05:45").unwrap().to_string(),
+            "+05:45"
+        );
+        assert!(BillingTimezone::parse("+25:00").is_err());
+        assert!(BillingTimezone::parse("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn test_posix_rule_europe() {
+        let tz = BillingTimezone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2025-03-30 01:00 UTC 开始夏令时
+        assert_eq!(tz.offset_at(utc(3, 30, 0, 59)), 3600);
+        assert_eq!(tz.offset_at(utc(3, 30, 1, 0)), 7200);
+        // 2025-10-26 01:00 UTC 结束夏令时
+        assert_eq!(tz.offset_at(utc(10, 26, 0, 59)), 7200);
+        assert_eq!(tz.offset_at(utc(10, 26, 1, 0)), 3600);
+    }
+
+    #[test]
+    fn test_posix_rule_america() {
+        let tz = BillingTimezone::parse("PST8PDT,M3.2.0,M11.1.0").unwrap();
+        // 2025-03-09 02:00 PST = 10:00 UTC
+        assert_eq!(tz.offset_at(utc(3, 9, 9, 59)), -8 * 3600);
+        assert_eq!(tz.offset_at(utc(3, 9, 10, 0)), -7 * 3600);
+        // 2025-11-02 02:00 PDT = 09:00 UTC
+        assert_eq!(tz.offset_at(utc(11, 2, 8, 59)), -7 * 3600);
+        assert_eq!(tz.offset_at(utc(11, 2, 9, 0)), -8 * 3600);
+    }
+
+    #[test]
+    fn test_posix_rule_southern_hemisphere() {
+        let tz = BillingTimezone::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(tz.offset_at(utc(1, 15, 0, 0)), 11 * 3600);
+        assert_eq!(tz.offset_at(utc(7, 1, 0, 0)), 10 * 3600);
+        // 2025-04-06 03:00 AEDT = 2025-04-05 16:00 UTC
+        assert_eq!(tz.offset_at(utc(4, 5, 15, 59)), 11 * 3600);
+        assert_eq!(tz.offset_at(utc(4, 5, 16, 0)), 10 * 3600);
+    }
+
+    #[test]
+    fn test_iana_zone() {
+        // 依赖系统 zoneinfo 数据库，缺失时跳过
+        let Ok(tz) = BillingTimezone::parse("Europe/Berlin") else {
+            return;
+        };
+        assert_eq!(tz.to_string(), "Europe/Berlin");
+        assert_eq!(tz.offset_at(utc(1, 1, 0, 0)), 3600);
+        assert_eq!(tz.offset_at(utc(3, 30, 0, 59)), 3600);
+        assert_eq!(tz.offset_at(utc(3, 30, 1, 0)), 7200);
+        assert_eq!(tz.offset_at(utc(10, 26, 1, 0)), 3600);
+    }
+}
@@ -1,58 +1,111 @@
-use crate::config::LogLevel;
-use crate::rustls_config::create_dangerous_config;
-use log::{Level, info};
-use std::sync::Arc;
+use crate::config::{Config, LogLevel};
+use crate::proxy::{ProxySettings, connect_url};
+use crate::rustls_config::panel_tls_config;
+use log::{Level, LevelFilter, info};
 use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::time::timeout;
 use tokio_tungstenite::{
-    Connector, MaybeTlsStream, WebSocketStream, connect_async, connect_async_tls_with_config,
+    Connector, MaybeTlsStream, WebSocketStream, client_async_tls_with_config,
 };
-use url::{ParseError, Url};
+use url::Url;
 
 pub fn init_logger(log_level: LogLevel) {
     #[cfg(target_os = "windows")]
     simple_logger::set_up_windows_color_terminal();
 
-    match log_level {
-        LogLevel::Error => simple_logger::init_with_level(Level::Error).unwrap(),
-        LogLevel::Warn => simple_logger::init_with_level(Level::Warn).unwrap(),
-        LogLevel::Info => simple_logger::init_with_level(Level::Info).unwrap(),
-        LogLevel::Debug => simple_logger::init_with_level(Level::Debug).unwrap(),
-        LogLevel::Trace => simple_logger::init_with_level(Level::Trace).unwrap(),
-    }
+    // Logger 本身不过滤，由全局最大等级控制，便于热重载时调整
+    simple_logger::init_with_level(Level::Trace).unwrap();
+    set_log_level(log_level);
+}
+
+/// 调整日志等级，立即生效
+pub fn set_log_level(log_level: LogLevel) {
+    log::set_max_level(match log_level {
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    });
 }
 
 #[derive(Debug, Clone)]
 pub struct ConnectionUrls {
+    /// 主端地址 (不含 token)，用于日志与 `info`
+    pub endpoint: String,
     pub basic_info: String,
     pub exec_callback: String,
     pub ws_terminal: String,
     pub ws_real_time: String,
 }
 
-pub fn build_urls(
+/// 根据配置构造主端地址，是否使用 TLS 由 URL scheme 决定
+///
+/// `http_server` 有多个地址时按顺序返回每个地址，第一个为主地址。
+pub fn build_urls(config: &Config) -> Result<Vec<ConnectionUrls>, String> {
+    if config.http_server.is_empty() {
+        return Err("缺少 http_server".to_string());
+    }
+    if !config.ws_server.is_empty() && config.ws_server.len() != config.http_server.len() {
+        return Err(format!(
+            "ws_server 的地址数量 ({}) 必须与 http_server ({}) 相同",
+            config.ws_server.len(),
+            config.http_server.len()
+        ));
+    }
+    config
+        .http_server
+        .iter()
+        .enumerate()
+        .map(|(index, http_server)| {
+            build_endpoint_urls(
+                config,
+                http_server,
+                config.ws_server.get(index).map(String::as_str),
+            )
+        })
+        .collect()
+}
+
+/// 构造单个主端地址的各个 URL，未设置 `ws_server` 时由 `http_server` 推断
+fn build_endpoint_urls(
+    config: &Config,
     http_server: &str,
-    ws_server: Option<&String>,
-    token: &str,
-) -> Result<ConnectionUrls, ParseError> {
+    ws_server: Option<&str>,
+) -> Result<ConnectionUrls, String> {
+    let token = &config.token;
+
     // 1. 构造 http_url_base
-    let http_url = Url::parse(http_server)?;
+    let http_url = Url::parse(http_server).map_err(|e| format!("http_server 无效: {e}"))?;
+    if !matches!(http_url.scheme(), "http" | "https") {
+        return Err(format!(
+            "http_server 不支持的 scheme: {} (应为 http 或 https)",
+            http_url.scheme()
+        ));
+    }
     let http_url_base = http_url.as_str().trim_end_matches('/');
 
     // 2. 构造 ws_url_base
     let ws_url = if let Some(ws) = ws_server {
-        Url::parse(ws)?
+        let ws_url = Url::parse(ws).map_err(|e| format!("ws_server 无效: {e}"))?;
+        if !matches!(ws_url.scheme(), "ws" | "wss") {
+            return Err(format!(
+                "ws_server 不支持的 scheme: {} (应为 ws 或 wss)",
+                ws_url.scheme()
+            ));
+        }
+        ws_url
     } else {
         let mut ws_url = http_url.clone();
-        match ws_url.scheme() {
-            "http" => ws_url.set_scheme("ws").unwrap(),
-            "https" => ws_url.set_scheme("wss").unwrap(),
-            other => panic!("不支持的 scheme: {other}"),
-        }
+        let scheme = if http_url.scheme() == "https" { "wss" } else { "ws" };
+        ws_url
+            .set_scheme(scheme)
+            .map_err(|()| format!("http_server 无法转换为 WebSocket 地址: {http_url}"))?;
         ws_url
     };
     let ws_url_base = ws_url.as_str().trim_end_matches('/').to_string();
+    check_tls_settings(config, &http_url, &ws_url)?;
 
     // 3. 构造各个最终 URL
     let basic_info_url = format!("{http_url_base}/api/clients/uploadBasicInfo?token={token}");
@@ -61,6 +114,7 @@ pub fn build_urls(
     let ws_real_time_url = format!("{ws_url_base}/api/clients/report?token={token}");
 
     let connection_urls = ConnectionUrls {
+        endpoint: http_url_base.to_string(),
         basic_info: basic_info_url,
         exec_callback: exec_callback_url,
         ws_terminal: ws_terminal_url,
@@ -72,58 +126,121 @@ pub fn build_urls(
     Ok(connection_urls)
 }
 
+/// 检查 TLS 相关配置与 URL scheme 是否矛盾
+fn check_tls_settings(config: &Config, http_url: &Url, ws_url: &Url) -> Result<(), String> {
+    let http_tls = http_url.scheme() == "https";
+    let ws_tls = ws_url.scheme() == "wss";
+
+    // tls = true 表示要求加密，任一地址为明文都视为配置错误
+    if config.tls {
+        if !http_tls {
+            return Err(format!(
+                "tls = true 但 http_server 使用明文地址 {http_url}，请改为 https:// 或关闭 tls"
+            ));
+        }
+        if !ws_tls {
+            return Err(format!(
+                "tls = true 但 WebSocket 使用明文地址 {ws_url}，请改为 wss:// 或关闭 tls"
+            ));
+        }
+    }
+
+    if !http_tls && !ws_tls {
+        if !config.pinned_pubkey.is_empty() {
+            return Err("pinned_pubkey 仅对 https/wss 地址生效，但主端地址均为明文".to_string());
+        }
+        if config.client_cert.is_some() {
+            return Err("client_cert 仅对 https/wss 地址生效，但主端地址均为明文".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// 连接主端 WebSocket，TLS 设置 (CA、公钥固定、客户端证书) 见 [`panel_tls_config`]，
+/// 代理设置见 [`ProxySettings`]
 pub async fn connect_ws(
     url: &str,
-    tls: bool,
-    skip_verify: bool,
+    config: &Config,
 ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, String> {
     let connection_timeout = Duration::from_secs(10);
+    let proxy = ProxySettings::from_config(config);
 
-    if tls {
-        if skip_verify {
-            timeout(
-                connection_timeout,
-                connect_async_tls_with_config(
-                    url,
-                    None,
-                    false,
-                    Some(Connector::Rustls(Arc::new(create_dangerous_config()))),
-                ),
-            )
-            .await
-            .map_err(|_| "WebSocket 连接超时".to_string())?
-            .map(|ws| ws.0)
-            .map_err(|_| "无法创立 WebSocket 连接".to_string())
-        } else {
-            timeout(
-                connection_timeout,
-                connect_async_tls_with_config(url, None, false, None),
-            )
+    // wss 地址始终使用主端 TLS 配置，ws 地址为明文连接
+    let connector = if url.starts_with("wss://") {
+        Some(Connector::Rustls(panel_tls_config(config)?))
+    } else {
+        None
+    };
+    let connect = async {
+        let stream = connect_url(url, &proxy, connection_timeout).await?;
+        client_async_tls_with_config(url, stream, None, connector)
             .await
-            .map_err(|_| "WebSocket 连接超时".to_string())?
-            .map(|ws| ws.0)
-            .map_err(|_| "无法创立 WebSocket 连接".into())
-        }
+            .map_err(|e| format!("无法创立 WebSocket 连接: {e}"))
+    };
+    timeout(connection_timeout, connect)
+        .await
+        .map_err(|_| "WebSocket 连接超时".to_string())?
+        .map(|ws| ws.0)
+}
+
+/// 检查主端地址是否可用: 建立 TCP 连接 (经由代理时建立隧道)，wss 地址再完成 TLS 握手
+///
+/// 握手同样校验证书与 `pinned_pubkey`。不发送任何请求，不会以 Agent 身份登录主端。
+pub async fn probe_endpoint(url: &str, config: &Config) -> Result<(), String> {
+    let connection_timeout = Duration::from_secs(10);
+    let proxy = ProxySettings::from_config(config);
+    let tls = if url.starts_with("wss://") {
+        Some(panel_tls_config(config)?)
     } else {
-        timeout(connection_timeout, connect_async(url))
+        None
+    };
+    let probe = async {
+        let stream = connect_url(url, &proxy, connection_timeout).await?;
+        let Some(tls) = tls else {
+            return Ok(());
+        };
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .ok_or("地址缺少主机名")?;
+        let stream = stream.into_std().map_err(|e| e.to_string())?;
+        tokio::task::spawn_blocking(move || tls_handshake(stream, tls, &host, connection_timeout))
             .await
-            .map_err(|_| "WebSocket 连接超时".to_string())?
-            .map(|ws| ws.0)
-            .map_err(|_| "无法创立 WebSocket 连接".to_string())
+            .map_err(|e| e.to_string())?
+    };
+    timeout(connection_timeout, probe)
+        .await
+        .map_err(|_| "连接超时".to_string())?
+}
+
+/// 在已建立的连接上完成 TLS 握手后关闭
+fn tls_handshake(
+    mut stream: std::net::TcpStream,
+    tls: std::sync::Arc<rustls::ClientConfig>,
+    host: &str,
+    timeout: Duration,
+) -> Result<(), String> {
+    stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+    let name = rustls_pki_types::ServerName::try_from(host).map_err(|e| e.to_string())?;
+    let mut conn = rustls::ClientConnection::new(tls, name).map_err(|e| e.to_string())?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)
+            .map_err(|e| format!("TLS 握手失败: {e}"))?;
     }
+    conn.send_close_notify();
+    let _ = conn.complete_io(&mut stream);
+    Ok(())
 }
 
+/// 访问主端 HTTP 接口 (Basic Info 上报、Exec 回调) 的 ureq Agent
 #[cfg(feature = "ureq-support")]
-pub fn create_ureq_agent(disable_verification: bool) -> ureq::Agent {
-    let config = ureq::Agent::config_builder()
-        .tls_config(
-            ureq::tls::TlsConfig::builder()
-                .disable_verification(disable_verification)
-                .build(),
-        )
-        .timeout_global(Some(Duration::from_secs(5)))
-        .build();
-    config.new_agent()
+pub fn panel_ureq_agent(config: &Config) -> Result<ureq::Agent, String> {
+    let tls = panel_tls_config(config)?;
+    let proxy = ProxySettings::from_config(config);
+    Ok(crate::rustls_config::ureq_agent(tls, proxy, Some(Duration::from_secs(5))))
 }
 
 #[cfg(feature = "nyquest-support")]
@@ -137,3 +254,179 @@ pub fn create_nyquest_client(disable_verification: bool) -> nyquest::BlockingCli
     }
     client.build_blocking().unwrap()
 }
+
+/// 访问主端 HTTP 接口的 nyquest 客户端
+///
+/// nyquest 无法应用自定义 CA、公钥固定、客户端证书与 `proxy` 配置项，代理只能通过后端
+/// 自身支持的 `HTTPS_PROXY` 等环境变量设置。
+#[cfg(feature = "nyquest-support")]
+pub fn panel_nyquest_client(config: &Config) -> Result<nyquest::BlockingClient, String> {
+    if config.ca_file.is_some() || !config.pinned_pubkey.is_empty() || config.client_cert.is_some()
+    {
+        return Err("nyquest 后端不支持 ca_file、pinned_pubkey 与 client_cert".to_string());
+    }
+    if config.proxy.is_some() {
+        return Err("nyquest 后端不支持 proxy 配置项，请使用 HTTPS_PROXY 环境变量".to_string());
+    }
+    Ok(create_nyquest_client(config.ignore_unsafe_cert))
+}
+
+/// 将紧凑的 JSON 文本格式化为带缩进的多行文本
+pub fn pretty_json(json: &str) -> String {
+    let mut out = String::with_capacity(json.len() * 2);
+    let mut indent = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = json.chars().peekable();
+
+    let newline = |out: &mut String, indent: usize| {
+        out.push('\n');
+        out.push_str(&"  ".repeat(indent));
+    };
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '{' | '[' => {
+                out.push(c);
+                // 空对象与空数组保持在同一行
+                if matches!(chars.peek(), Some('}' | ']')) {
+                    out.push(chars.next().unwrap_or_default());
+                } else {
+                    indent += 1;
+                    newline(&mut out, indent);
+                }
+            }
+            '}' | ']' => {
+                indent = indent.saturating_sub(1);
+                newline(&mut out, indent);
+                out.push(c);
+            }
+            ',' => {
+                out.push(c);
+                newline(&mut out, indent);
+            }
+            ':' => out.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_probe_endpoint_does_not_send_requests() {
+        use tokio::io::AsyncReadExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = format!("ws://127.0.0.1:{port}/api/clients/report?token=t");
+        let config = Config::default();
+
+        let accept = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let _ = socket.read_to_end(&mut received).await;
+            received
+        });
+        assert_eq!(probe_endpoint(&url, &config).await, Ok(()));
+        // 探测只建立连接，主端收不到任何请求
+        assert!(accept.await.unwrap().is_empty());
+        assert!(probe_endpoint(&url, &config).await.is_err());
+    }
+
+    #[test]
+    fn test_pretty_json() {
+        let json = r#"{"a":[1,2],"b":{},"c":"x,{\"y\":[]}","d":null}"#;
+        let expected = r#"{
+  "a": [
+    1,
+    2
+  ],
+  "b": {},
+  "c": "x,{\"y\":[]}",
+  "d": null
+}"#;
+        assert_eq!(pretty_json(json), expected);
+    }
+
+    #[test]
+    fn test_build_urls_scheme() {
+        let mut config = Config {
+            http_server: vec!["https://panel.example.com/".to_string()],
+            token: "t".to_string(),
+            ..Config::default()
+        };
+        let urls = build_urls(&config).unwrap();
+        assert_eq!(
+            urls[0].ws_real_time,
+            "wss://panel.example.com/api/clients/report?token=t"
+        );
+
+        // scheme 为准: tls = false 不影响 https/wss
+        config.ws_server = vec!["wss://ws.example.com".to_string()];
+        assert!(build_urls(&config).is_ok());
+
+        // tls = true 与明文地址矛盾
+        config.tls = true;
+        config.ws_server = vec!["ws://ws.example.com".to_string()];
+        assert!(build_urls(&config).is_err());
+        config.ws_server = vec!["https://ws.example.com".to_string()];
+        assert!(build_urls(&config).is_err());
+
+        config.tls = false;
+        config.ws_server = Vec::new();
+        config.http_server = vec!["http://panel.example.com".to_string()];
+        config.pinned_pubkey = vec![format!("sha256//{}=", "A".repeat(43))];
+        assert!(build_urls(&config).is_err());
+        config.http_server = vec!["ftp://panel.example.com".to_string()];
+        assert!(build_urls(&config).is_err());
+    }
+
+    #[test]
+    fn test_build_urls_endpoints() {
+        let mut config = Config {
+            http_server: vec![
+                "https://a.example.com".to_string(),
+                "https://b.example.com/".to_string(),
+            ],
+            token: "t".to_string(),
+            ..Config::default()
+        };
+        let endpoints: Vec<_> = build_urls(&config)
+            .unwrap()
+            .into_iter()
+            .map(|urls| urls.endpoint)
+            .collect();
+        assert_eq!(
+            endpoints,
+            ["https://a.example.com", "https://b.example.com"]
+        );
+
+        // ws_server 与 http_server 按位置对应
+        config.ws_server = vec!["wss://ws-a.example.com".to_string()];
+        assert!(build_urls(&config).is_err());
+        config.ws_server.push("wss://ws-b.example.com".to_string());
+        assert_eq!(
+            build_urls(&config).unwrap()[1].ws_real_time,
+            "wss://ws-b.example.com/api/clients/report?token=t"
+        );
+    }
+}
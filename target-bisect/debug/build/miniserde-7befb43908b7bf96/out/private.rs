@@ -0,0 +1,5 @@
+#[doc(hidden)]
+pub mod __private43 {
+    #[doc(hidden)]
+    pub use crate::export::*;
+}